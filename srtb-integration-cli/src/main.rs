@@ -1,9 +1,17 @@
 use rfd::FileDialog;
 use srtb_integration::{
-    ChromaIntegrator, Integrator, RawSrtbFile, SpeedsIntegrator, SpinDifficulty,
+    ChromaIntegrator, FileLoader, IncludeContext, IntegrateOptions, Integrator, RawSrtbFile,
+    SaveOptions, SpeedsIntegrator, SpinDifficulty, SrtbFormat,
 };
 use std::{fs, io::Write};
 
+/// Keep the previous version of a chart around when overwriting it.
+const SAVE_OPTIONS: SaveOptions = SaveOptions {
+    backups: 1,
+    format: SrtbFormat::Compact,
+    canonical_payloads: false,
+};
+
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
     println!("Please select the integration mode");
@@ -32,8 +40,17 @@ fn main() {
     println!("Selected: {}", file.display());
     let mut chart = RawSrtbFile::open(&file).unwrap();
 
+    let mut diffs = match chart.difficulties() {
+        Ok(diffs) => diffs,
+        Err(e) => {
+            eprintln!("Could not read the difficulties of the chart: {}", e);
+            std::process::exit(1);
+        }
+    };
+    diffs.push(SpinDifficulty::AllDifficulties);
+
     println!("Please select a difficulty");
-    for (i, diff) in SpinDifficulty::ALL.iter().enumerate() {
+    for (i, diff) in diffs.iter().enumerate() {
         println!("{}. {}", i + 1, diff);
     }
     print!("> ");
@@ -45,15 +62,14 @@ fn main() {
         .expect("failed to read from stdin");
 
     let opt: usize = buf.trim().parse().expect("invalid integer");
-    let diff = *SpinDifficulty::ALL
-        .get(opt - 1)
-        .expect("invalid difficulty selected");
+    let diff = *diffs.get(opt - 1).expect("invalid difficulty selected");
 
     println!("Please select an action");
     println!("1. Integrate");
     println!("2. Extract");
     println!("3. Remove");
-    println!("4. Exit");
+    println!("4. Compare with another chart");
+    println!("5. Exit");
     print!("> ");
     let mut buf = String::new();
     std::io::stdout().flush().expect("failed to flush stdout");
@@ -71,14 +87,28 @@ fn main() {
                 .pick_file()
                 .unwrap();
             println!("Selected {}", extra_file.display());
-            let data = fs::read_to_string(extra_file).unwrap();
-            integrator.integrate(&mut chart, &data, diff).unwrap();
+            let data = fs::read_to_string(&extra_file).unwrap();
+            let includes = IncludeContext {
+                path: &extra_file,
+                loader: &FileLoader,
+            };
+            integrator
+                .integrate_with_includes(
+                    &mut chart,
+                    &data,
+                    diff,
+                    IntegrateOptions::default(),
+                    Some(&includes),
+                )
+                .unwrap();
             println!("Integration complete! Please select a saving location");
             let save_location = FileDialog::new()
                 .add_filter("Spin Rhythm Track Bundle", &["srtb"])
                 .save_file()
                 .unwrap();
-            chart.save(&save_location).unwrap();
+            chart
+                .save_with_options(&save_location, SAVE_OPTIONS)
+                .unwrap();
             println!("Saved to {}", save_location.display());
         }
         2 => {
@@ -99,10 +129,28 @@ fn main() {
                 .add_filter("Spin Rhythm Track Bundle", &["srtb"])
                 .save_file()
                 .unwrap();
-            chart.save(&save_location).unwrap();
+            chart
+                .save_with_options(&save_location, SAVE_OPTIONS)
+                .unwrap();
             println!("Saved to {}", save_location.display());
         }
-        4 => {}
+        4 => {
+            println!("Please select the chart to compare with");
+            let other_file = FileDialog::new()
+                .add_filter("Spin Rhythm Track Bundle", &["srtb"])
+                .pick_file()
+                .unwrap();
+            println!("Selected: {}", other_file.display());
+            let other_chart = RawSrtbFile::open(&other_file).unwrap();
+            let changes = integrator.changes(&chart, &other_chart, diff).unwrap();
+            if changes.is_empty() {
+                println!("No differences found");
+            }
+            for change in changes {
+                println!("{}", change);
+            }
+        }
+        5 => {}
         _ => unreachable!(),
     }
 }
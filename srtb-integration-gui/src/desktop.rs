@@ -5,12 +5,20 @@ use iced::{
     Alignment, Length, Size,
 };
 use srtb_integration::{
-    ChromaIntegrator, IntegrationError, Integrator, RawSrtbFile, SpeedsIntegrator, SpinDifficulty,
+    FileLoader, IncludeContext, IntegrateOptions, IntegrationError, IntegratorKind, RawSrtbFile,
+    SaveOptions, SpinDifficulty, SrtbFormat,
 };
 use strum::Display;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Keep the previous version of a chart around when overwriting it.
+const SAVE_OPTIONS: SaveOptions = SaveOptions {
+    backups: 1,
+    format: SrtbFormat::Compact,
+    canonical_payloads: false,
+};
+
 pub fn program() -> iced::Result {
     iced::application(App::default, App::update, App::view)
         .title(App::title)
@@ -33,24 +41,6 @@ impl FilePathExt for PathBuf {
     }
 }
 
-#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
-enum IntegratorKind {
-    #[default]
-    Speeds,
-    Chroma,
-}
-
-impl IntegratorKind {
-    const ALL: [Self; 2] = [Self::Speeds, Self::Chroma];
-
-    pub fn ext(self) -> &'static str {
-        match self {
-            IntegratorKind::Speeds => "speeds",
-            IntegratorKind::Chroma => "chroma",
-        }
-    }
-}
-
 #[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
 enum OperationKind {
     #[default]
@@ -108,6 +98,11 @@ impl App {
                 self.input_file = rfd::FileDialog::new()
                     .add_filter("Spin Rhythm Track Bundle", &["srtb"])
                     .pick_file();
+                let diffs = self.chart_difficulties();
+                if self.difficulty.is_some_and(|d| !diffs.contains(&d)) {
+                    self.difficulty = None;
+                }
+                self.difficulty_state = combo_box::State::new(diffs);
             }
             SelectDifficulty(diff) => {
                 self.difficulty = Some(diff);
@@ -259,13 +254,25 @@ impl App {
             .into()
     }
 
+    /// Difficulties offered for the selected chart. Falls back to every difficulty if the chart
+    /// can't be read, in which case processing will report the actual error.
+    fn chart_difficulties(&self) -> Vec<SpinDifficulty> {
+        let Some(mut diffs) = self
+            .input_file
+            .as_ref()
+            .and_then(|f| RawSrtbFile::open(f).ok())
+            .and_then(|chart| chart.difficulties().ok())
+        else {
+            return SpinDifficulty::ALL.to_vec();
+        };
+        diffs.push(SpinDifficulty::AllDifficulties);
+        diffs
+    }
+
     fn process(&self) -> Result<(), IntegrationError> {
         // Lots of unwrapping: this is bad practice, but it is checked before this function runs.
         let integrator_kind = self.integrator_kind.unwrap();
-        let integrator: Box<dyn Integrator> = match integrator_kind {
-            IntegratorKind::Speeds => Box::new(SpeedsIntegrator),
-            IntegratorKind::Chroma => Box::new(ChromaIntegrator),
-        };
+        let integrator = integrator_kind.integrator();
 
         let diff = self.difficulty.unwrap();
         let operation = self.operation.unwrap();
@@ -275,15 +282,25 @@ impl App {
         match operation {
             OperationKind::Integrate => {
                 // This one is also checked
-                let extra_data = self.extra_file.as_ref().unwrap();
+                let extra_file = self.extra_file.as_ref().unwrap();
                 let extra_data =
-                    fs::read_to_string(extra_data).map_err(IntegrationError::IoError)?;
-                integrator.integrate(&mut chart, &extra_data, diff)?;
+                    fs::read_to_string(extra_file).map_err(IntegrationError::IoError)?;
+                let includes = IncludeContext {
+                    path: extra_file,
+                    loader: &FileLoader,
+                };
+                integrator.integrate_with_includes(
+                    &mut chart,
+                    &extra_data,
+                    diff,
+                    IntegrateOptions::default(),
+                    Some(&includes),
+                )?;
                 let dest_file = rfd::FileDialog::new()
                     .add_filter("Spin Rhythm Track Bundle", &["srtb"])
                     .save_file()
                     .ok_or(IntegrationError::Cancelled)?;
-                chart.save(dest_file)?;
+                chart.save_with_options(dest_file, SAVE_OPTIONS)?;
             }
             OperationKind::Extract => {
                 let data = integrator.extract(&chart, diff)?;
@@ -302,7 +319,7 @@ impl App {
                     .add_filter("Spin Rhythm Track Bundle", &["srtb"])
                     .save_file()
                     .ok_or(IntegrationError::Cancelled)?;
-                chart.save(dest_file)?;
+                chart.save_with_options(dest_file, SAVE_OPTIONS)?;
             }
         }
 
@@ -0,0 +1,137 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde_json::json;
+use srtb_integration::RawSrtbFile;
+
+const TRACK_DATA_COUNT: usize = 6;
+const NOTES_PER_TRACK_DATA: usize = 40_000;
+const MOD_KEY_COUNT: usize = 2_000;
+
+/// Builds a chart with a few multi-megabyte track data blobs and many small mod keys.
+fn synthetic_chart() -> Vec<u8> {
+    let mut unity_objects = vec![];
+    let mut large_strings = vec![];
+    for diff in 0..TRACK_DATA_COUNT {
+        let key = format!("SO_TrackData_TrackData_{}", diff);
+        let notes: Vec<_> = (0..NOTES_PER_TRACK_DATA)
+            .map(|i| {
+                json!({
+                    "time": i as f32 * 0.125,
+                    "type": i % 8,
+                    "colorIndex": i % 2,
+                    "column": (i % 9) as i32 - 4,
+                    "m_size": 0,
+                })
+            })
+            .collect();
+        let data = json!({ "difficultyType": diff + 2, "notes": notes });
+        unity_objects.push(json!({ "key": key, "jsonKey": key, "fullType": "TrackData" }));
+        large_strings.push(json!({ "key": key, "val": data.to_string() }));
+    }
+    for i in 0..MOD_KEY_COUNT {
+        large_strings.push(json!({ "key": format!("SomeMod_Key_{}", i), "val": "{}" }));
+    }
+    let chart = json!({
+        "unityObjectValuesContainer": { "values": unity_objects },
+        "largeStringValuesContainer": { "values": large_strings },
+        "clipInfoCount": 0,
+    });
+    serde_json::to_vec(&chart).unwrap()
+}
+
+/// The large string values as the chart stores them, accessed like before the key index: a
+/// linear scan that clones the value found.
+struct LinearScan(Vec<(String, String)>);
+
+impl LinearScan {
+    fn new(bytes: &[u8]) -> Self {
+        let chart: serde_json::Value = serde_json::from_slice(bytes).unwrap();
+        let values = chart["largeStringValuesContainer"]["values"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| {
+                let key = v["key"].as_str().unwrap().to_string();
+                (key, v["val"].as_str().unwrap().to_string())
+            })
+            .collect();
+        Self(values)
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.0.push((key.to_string(), value.to_string())),
+        }
+    }
+}
+
+/// Compares the indexed access of [`RawSrtbFile`] with the linear scan it replaced.
+fn large_string_access(c: &mut Criterion) {
+    let bytes = synthetic_chart();
+    let mut chart = RawSrtbFile::from_bytes(&bytes).unwrap();
+    let mut linear = LinearScan::new(&bytes);
+    let last_track_data = format!("SO_TrackData_TrackData_{}", TRACK_DATA_COUNT - 1);
+    let last_mod_key = format!("SomeMod_Key_{}", MOD_KEY_COUNT - 1);
+
+    let mut group = c.benchmark_group("get track data");
+    group.bench_function("indexed", |b| {
+        b.iter(|| {
+            black_box(chart.get_large_string_value(black_box(&last_track_data))).map(|v| v.len())
+        })
+    });
+    group.bench_function("linear scan", |b| {
+        b.iter(|| black_box(linear.get(black_box(&last_track_data))).map(|v| v.len()))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("get last mod key");
+    group.bench_function("indexed", |b| {
+        b.iter(|| {
+            black_box(chart.get_large_string_value(black_box(&last_mod_key))).map(|v| v.len())
+        })
+    });
+    group.bench_function("linear scan", |b| {
+        b.iter(|| black_box(linear.get(black_box(&last_mod_key))).map(|v| v.len()))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("get every mod key");
+    group.bench_function("indexed", |b| {
+        b.iter(|| {
+            for i in 0..MOD_KEY_COUNT {
+                let key = format!("SomeMod_Key_{}", i);
+                black_box(chart.get_large_string_value(&key).map(|v| v.len()));
+            }
+        })
+    });
+    group.bench_function("linear scan", |b| {
+        b.iter(|| {
+            for i in 0..MOD_KEY_COUNT {
+                let key = format!("SomeMod_Key_{}", i);
+                black_box(linear.get(&key).map(|v| v.len()));
+            }
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("set last mod key");
+    group.bench_function("indexed", |b| {
+        b.iter(|| chart.set_large_string_value(black_box(&last_mod_key), black_box("{}")))
+    });
+    group.bench_function("linear scan", |b| {
+        b.iter(|| linear.set(black_box(&last_mod_key), black_box("{}")))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, large_string_access);
+criterion_main!(benches);
@@ -1,18 +1,24 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::{Display, Formatter, Write},
+    str::FromStr,
 };
 
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use crate::{
     color::{HslColor, RgbColor},
-    IntegrationError, Integrator, ParsingError, RawSrtbFile, SpinDifficulty,
+    compare::compare_triggers,
+    expr::{check_name, eval, eval_int, eval_list, split_arguments, LoopVariable, Names},
+    layer::overlay_triggers,
+    payload::{Payload, PayloadIntegrator},
+    schema::{default_field, Migration},
+    timing::{resolve_time, ScriptTime, TimingMap},
+    ExtractOptions, IntegrationError, ParsingError, Retime, TriggerChange,
 };
 
-const SRTB_KEY: &str = "SpeenChroma_ChromaTriggers";
-
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum ChromaNoteType {
     NoteA,
@@ -35,21 +41,6 @@ impl ChromaNoteType {
         ChromaNoteType::Ancillary,
     ];
 
-    pub fn from_str(note: &str) -> Result<ChromaNoteType, ParsingError> {
-        use ChromaNoteType::*;
-        let note = match note.to_lowercase().as_str() {
-            "notea" => NoteA,
-            "noteb" => NoteB,
-            "beat" => Beat,
-            "spinleft" | "leftspin" => SpinLeft,
-            "spinright" | "rightspin" => SpinRight,
-            "scratch" => Scratch,
-            "ancillary" | "highlights" => Ancillary,
-            _ => return Err(ParsingError::InvalidNote(note.into())),
-        };
-        Ok(note)
-    }
-
     pub fn from_str_multiple(notes: &str) -> Result<HashSet<ChromaNoteType>, ParsingError> {
         let mut set = HashSet::new();
         for note in notes.split(",") {
@@ -83,6 +74,25 @@ impl ChromaNoteType {
     }
 }
 
+impl FromStr for ChromaNoteType {
+    type Err = ParsingError;
+
+    fn from_str(note: &str) -> Result<ChromaNoteType, ParsingError> {
+        use ChromaNoteType::*;
+        let note = match note.to_lowercase().as_str() {
+            "notea" => NoteA,
+            "noteb" => NoteB,
+            "beat" => Beat,
+            "spinleft" | "leftspin" => SpinLeft,
+            "spinright" | "rightspin" => SpinRight,
+            "scratch" => Scratch,
+            "ancillary" | "highlights" => Ancillary,
+            _ => return Err(ParsingError::InvalidNote(note.into())),
+        };
+        Ok(note)
+    }
+}
+
 impl Display for ChromaNoteType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         use ChromaNoteType::*;
@@ -99,16 +109,33 @@ impl Display for ChromaNoteType {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct ChromaTrigger {
     time: f32,
     duration: f32,
     start_color: HslColor,
     end_color: HslColor,
+    /// Fields this tool doesn't know about, kept as they are.
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
 impl ChromaTrigger {
+    /// Colour at `time` while fading, each component changing linearly.
+    fn color_at(&self, time: f32) -> HslColor {
+        if self.duration <= 0. {
+            return self.end_color;
+        }
+        let progress = ((time - self.time) / self.duration).clamp(0., 1.);
+        let lerp = |from: f32, to: f32| from + (to - from) * progress;
+        HslColor {
+            h: lerp(self.start_color.h, self.end_color.h),
+            s: lerp(self.start_color.s, self.end_color.s),
+            l: lerp(self.start_color.l, self.end_color.l),
+        }
+    }
+
     pub fn ensure_smooth_transition(&mut self) {
         if self.start_color.h == 0.
             && self.end_color.h != 0.
@@ -127,7 +154,7 @@ impl ChromaTrigger {
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
-struct ChromaTriggersData {
+pub(crate) struct ChromaTriggersData {
     note_a: Vec<ChromaTrigger>,
     note_b: Vec<ChromaTrigger>,
     beat: Vec<ChromaTrigger>,
@@ -135,13 +162,50 @@ struct ChromaTriggersData {
     spin_right: Vec<ChromaTrigger>,
     scratch: Vec<ChromaTrigger>,
     ancillary: Vec<ChromaTrigger>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
-fn make_key(diff: SpinDifficulty) -> String {
-    if diff == SpinDifficulty::AllDifficulties {
-        SRTB_KEY.to_string()
-    } else {
-        format!("{}_{}", SRTB_KEY, diff.to_string().to_uppercase())
+impl ChromaTriggersData {
+    fn note_triggers(&self, note_type: ChromaNoteType) -> &[ChromaTrigger] {
+        use ChromaNoteType::*;
+        match note_type {
+            NoteA => &self.note_a,
+            NoteB => &self.note_b,
+            Beat => &self.beat,
+            SpinLeft => &self.spin_left,
+            SpinRight => &self.spin_right,
+            Scratch => &self.scratch,
+            Ancillary => &self.ancillary,
+        }
+    }
+
+    fn note_triggers_mut(&mut self, note_type: ChromaNoteType) -> &mut Vec<ChromaTrigger> {
+        use ChromaNoteType::*;
+        match note_type {
+            NoteA => &mut self.note_a,
+            NoteB => &mut self.note_b,
+            Beat => &mut self.beat,
+            SpinLeft => &mut self.spin_left,
+            SpinRight => &mut self.spin_right,
+            Scratch => &mut self.scratch,
+            Ancillary => &mut self.ancillary,
+        }
+    }
+
+    fn triggers(&self) -> impl Iterator<Item = (ChromaNoteType, &ChromaTrigger)> {
+        use ChromaNoteType::*;
+        [
+            (NoteA, &self.note_a),
+            (NoteB, &self.note_b),
+            (Beat, &self.beat),
+            (SpinLeft, &self.spin_left),
+            (SpinRight, &self.spin_right),
+            (Scratch, &self.scratch),
+            (Ancillary, &self.ancillary),
+        ]
+        .into_iter()
+        .flat_map(|(note, triggers)| triggers.iter().map(move |t| (note, t)))
     }
 }
 
@@ -152,20 +216,39 @@ struct ChromaColorMaps {
 }
 
 impl ChromaColorMaps {
-    fn get_color(&self, color_str: &str) -> Result<HslColor, ParsingError> {
+    /// Parses a hex colour, an `hsl(hue, saturation, lightness)` colour whose components are
+    /// expressions, or a colour variable.
+    fn get_color(&self, color_str: &str, names: &Names) -> Result<HslColor, ParsingError> {
         let color_str = color_str.to_lowercase();
         if color_str.starts_with('#') {
             let col = RgbColor::from_hex_str(&color_str).map_err(ParsingError::ColorError)?;
             let col = HslColor::from(col);
             return Ok(col);
         }
+        if let Some(components) = color_str
+            .strip_prefix("hsl(")
+            .and_then(|c| c.strip_suffix(')'))
+        {
+            return match eval_list(components, names)?[..] {
+                [h, s, l] => Ok(HslColor {
+                    h: h.rem_euclid(360.) / 360.,
+                    s: s.clamp(0., 1.),
+                    l: l.clamp(0., 1.),
+                }),
+                _ => Err(ParsingError::InvalidHslColor(color_str)),
+            };
+        }
         self.variables
             .get(&color_str)
             .copied()
             .ok_or(ParsingError::ColorVariableNotFound(color_str))
     }
 
-    fn get_color_default_note(&self, color_str: &str) -> Result<HslColor, ParsingError> {
+    fn get_color_default_note(
+        &self,
+        color_str: &str,
+        names: &Names,
+    ) -> Result<HslColor, ParsingError> {
         let color_str = color_str.to_lowercase();
         if let Some(note_type) = color_str.strip_prefix("default") {
             let note_type = ChromaNoteType::from_str(note_type)?;
@@ -175,13 +258,14 @@ impl ChromaColorMaps {
                 .copied()
                 .ok_or(ParsingError::NoDefaultColorForNote(note_type.to_string()));
         }
-        self.get_color(&color_str)
+        self.get_color(&color_str, names)
     }
 
     fn get_color_default(
         &self,
         note_type: ChromaNoteType,
         color_str: &str,
+        names: &Names,
     ) -> Result<HslColor, ParsingError> {
         let color_str = color_str.to_lowercase();
         if color_str == "default" {
@@ -191,11 +275,14 @@ impl ChromaColorMaps {
                 .copied()
                 .ok_or(ParsingError::NoDefaultColorForNote(note_type.to_string()));
         }
-        self.get_color_default_note(&color_str)
+        self.get_color_default_note(&color_str, names)
     }
 }
 
-fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError> {
+fn lines_to_chroma(
+    lines: &[String],
+    timing: &TimingMap,
+) -> Result<ChromaTriggersData, IntegrationError> {
     let regex = Regex::new(r"(default)|([^a-zA-Z0-9\-_]+)").unwrap();
     let mut colors = ChromaColorMaps::default();
     let mut chroma_data = HashMap::new();
@@ -203,43 +290,32 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
         chroma_data.insert(note_type, vec![]);
     }
 
-    let lines: Vec<_> = content.lines().collect();
     let mut line_number = 0;
 
     let mut repeat_depth = 0;
     let mut repeat_counts = Vec::<i32>::new();
     let mut current_iterations = vec![];
-    let mut repeat_intervals = Vec::<f32>::new();
+    let mut repeat_intervals = Vec::<ScriptTime>::new();
     let mut goto_line_buf = vec![];
+    let mut loop_variables = Vec::<Option<LoopVariable>>::new();
+    let mut names = Names::new();
 
     macro_rules! get_time {
         ($time:expr) => {{
-            let mut time: f32 = $time.parse().map_err(|_| {
-                IntegrationError::ParsingError(
-                    line_number,
-                    ParsingError::InvalidFloat($time.into()),
-                )
-            })?;
-            let time = if repeat_depth > 0 {
-                for i in 0..repeat_depth {
-                    time += repeat_intervals[i] * current_iterations[i] as f32;
-                }
-                time
-            } else {
-                time
-            };
-            Ok::<f32, IntegrationError>(time)
+            ScriptTime::parse($time, timing, &names)
+                .and_then(|time| resolve_time(timing, time, &repeat_intervals, &current_iterations))
+                .map_err(|e| IntegrationError::ParsingError(line_number, e))
         }};
     }
 
     while line_number < lines.len() {
-        let line = lines[line_number];
+        let line = &lines[line_number];
         let line = line.trim().to_lowercase();
         if line.is_empty() || line.starts_with('#') {
             line_number += 1;
             continue;
         }
-        let line: Vec<_> = line.split_whitespace().collect();
+        let line = split_arguments(&line);
         if line.is_empty() || line[0].is_empty() {
             line_number += 1;
             continue;
@@ -256,7 +332,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                 let note_type = ChromaNoteType::from_str(line[1])
                     .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
                 let color = colors
-                    .get_color(line[2])
+                    .get_color(line[2], &names)
                     .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
                 chroma_data
                     .get_mut(&note_type)
@@ -266,6 +342,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                         duration: 0.,
                         start_color: color,
                         end_color: color,
+                        extra: Map::new(),
                     });
                 colors.default_colors.insert(note_type, color);
             }
@@ -277,16 +354,41 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                     ));
                 }
                 let variable_name = line[1].to_string();
-                let color = HslColor::from(RgbColor::from_hex_str(line[2]).map_err(|e| {
-                    IntegrationError::ParsingError(line_number, ParsingError::ColorError(e))
-                })?);
-                if regex.is_match(&variable_name) {
+                // Six hex digits are a colour even without `#`, as they always were: use an
+                // expression such as `(123456)` to set such a number.
+                let is_color = line[2].starts_with('#')
+                    || line[2].to_lowercase().starts_with("hsl(")
+                    || RgbColor::from_hex_str(line[2]).is_ok();
+                if !is_color {
+                    let value = check_name(&variable_name)
+                        .and_then(|_| eval(line[2], &names))
+                        .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
+                    names.insert(variable_name, value);
+                } else {
+                    let color = match RgbColor::from_hex_str(line[2]) {
+                        Ok(color) => Ok(HslColor::from(color)),
+                        Err(_) => colors.get_color(line[2], &names),
+                    }
+                    .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
+                    if regex.is_match(&variable_name) {
+                        return Err(IntegrationError::ParsingError(
+                            line_number,
+                            ParsingError::InvalidColorVariableName(variable_name),
+                        ));
+                    }
+                    colors.variables.insert(variable_name.to_string(), color);
+                }
+            }
+            "marker" => {
+                if line.len() < 3 {
                     return Err(IntegrationError::ParsingError(
                         line_number,
-                        ParsingError::InvalidColorVariableName(variable_name),
+                        ParsingError::MissingArguments,
                     ));
                 }
-                colors.variables.insert(variable_name.to_string(), color);
+                check_name(line[1]).map_err(|e| IntegrationError::ParsingError(line_number, e))?;
+                let time = get_time!(line[2])?;
+                names.insert(line[1].to_string(), time);
             }
             "instant" => {
                 if line.len() < 4 {
@@ -300,7 +402,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                 for note_type in note_types {
                     let time = get_time!(line[2])?;
                     let color = colors
-                        .get_color_default(note_type, line[3])
+                        .get_color_default(note_type, line[3], &names)
                         .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
                     chroma_data
                         .get_mut(&note_type)
@@ -310,6 +412,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                             duration: 0.,
                             start_color: color,
                             end_color: color,
+                            extra: Map::new(),
                         });
                 }
             }
@@ -371,6 +474,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                                     duration: 0.,
                                     start_color: second_col,
                                     end_color: second_col,
+                                    extra: Map::new(),
                                 });
                             chroma_data
                                 .get_mut(&second_note_type)
@@ -380,6 +484,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                                     duration: 0.,
                                     start_color: first_col,
                                     end_color: first_col,
+                                    extra: Map::new(),
                                 });
                         }
                     }
@@ -403,7 +508,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                             ));
                         }
                         let flash_col = colors
-                            .get_color(line[6])
+                            .get_color(line[6], &names)
                             .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
                         for (first_note_type, second_note_type) in
                             first_note_types.into_iter().zip(second_note_types)
@@ -437,6 +542,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                                     duration: end_time - start_time,
                                     start_color: flash_col,
                                     end_color: second_col,
+                                    extra: Map::new(),
                                 });
                             chroma_data
                                 .get_mut(&second_note_type)
@@ -446,6 +552,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                                     duration: end_time - start_time,
                                     start_color: flash_col,
                                     end_color: first_col,
+                                    extra: Map::new(),
                                 });
                         }
                     }
@@ -473,18 +580,18 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                 }
 
                 repeat_depth += 1;
-                repeat_counts.push(line[1].parse().map_err(|_| {
-                    IntegrationError::ParsingError(
-                        line_number,
-                        ParsingError::InvalidInt(line[1].into()),
-                    )
-                })?);
-                repeat_intervals.push(line[3].parse().map_err(|_| {
-                    IntegrationError::ParsingError(
-                        line_number,
-                        ParsingError::InvalidFloat(line[3].into()),
-                    )
-                })?);
+                repeat_counts.push(
+                    eval_int(line[1], &names)
+                        .map_err(|e| IntegrationError::ParsingError(line_number, e))?,
+                );
+                repeat_intervals.push(
+                    ScriptTime::parse_interval(line[3], &names)
+                        .map_err(|e| IntegrationError::ParsingError(line_number, e))?,
+                );
+                loop_variables.push(
+                    LoopVariable::parse(&line[4..], &mut names)
+                        .map_err(|e| IntegrationError::ParsingError(line_number, e))?,
+                );
                 current_iterations.push(0);
                 goto_line_buf.push(line_number);
             }
@@ -498,6 +605,9 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
 
                 current_iterations[repeat_depth - 1] += 1;
                 if current_iterations[repeat_depth - 1] < repeat_counts[repeat_depth - 1] {
+                    if let Some(variable) = &loop_variables[repeat_depth - 1] {
+                        variable.set(current_iterations[repeat_depth - 1], &mut names);
+                    }
                     line_number = goto_line_buf[repeat_depth - 1] + 1;
                     continue;
                 }
@@ -506,6 +616,9 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                 repeat_counts.pop();
                 repeat_intervals.pop();
                 goto_line_buf.pop();
+                if let Some(variable) = loop_variables.pop().flatten() {
+                    variable.end(&mut names);
+                }
                 current_iterations.pop();
             }
             "undo" => {
@@ -540,6 +653,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                                 duration: 0.,
                                 start_color: col,
                                 end_color: col,
+                                extra: Map::new(),
                             });
                         }
                     }
@@ -569,6 +683,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                                 duration: end_time - start_time,
                                 start_color,
                                 end_color,
+                                extra: Map::new(),
                             });
                         }
                     }
@@ -585,7 +700,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                         let end_time = get_time!(line[4])?;
                         for note_type in note_types {
                             let flash_color = colors
-                                .get_color_default(note_type, line[5])
+                                .get_color_default(note_type, line[5], &names)
                                 .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
                             let triggers = chroma_data.get_mut(&note_type).unwrap();
                             if triggers.len() < 2 {
@@ -600,6 +715,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                                 duration: end_time - start_time,
                                 start_color: flash_color,
                                 end_color,
+                                extra: Map::new(),
                             });
                         }
                     }
@@ -624,10 +740,10 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                 let end_time = get_time!(line[2])?;
                 for note_type in note_types {
                     let start_color = colors
-                        .get_color_default(note_type, line[3])
+                        .get_color_default(note_type, line[3], &names)
                         .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
                     let end_color = colors
-                        .get_color_default(note_type, line[4])
+                        .get_color_default(note_type, line[4], &names)
                         .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
                     chroma_data
                         .get_mut(&note_type)
@@ -637,6 +753,7 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
                             duration: end_time - start_time,
                             start_color,
                             end_color,
+                            extra: Map::new(),
                         });
                 }
             }
@@ -668,107 +785,204 @@ fn text_to_chroma(content: &str) -> Result<ChromaTriggersData, IntegrationError>
             spin_right: chroma_data.remove(&SpinRight).unwrap(),
             scratch: chroma_data.remove(&Scratch).unwrap(),
             ancillary: chroma_data.remove(&Ancillary).unwrap(),
+            extra: Map::new(),
         })
     }
 }
 
+fn trigger_to_line(note: ChromaNoteType, trigger: &ChromaTrigger) -> String {
+    let note = note.to_str_chroma();
+    let src_col = RgbColor::from(trigger.start_color).hex();
+    let dst_col = RgbColor::from(trigger.end_color).hex();
+    if trigger.time == 0. && trigger.duration == 0. {
+        format!("Start {} {}", note, src_col)
+    } else if trigger.duration == 0. {
+        format!("Instant {} {:?} {}", note, trigger.time, dst_col)
+    } else {
+        format!(
+            "{} {:?} {:?} {} {}",
+            note,
+            trigger.time,
+            trigger.time + trigger.duration,
+            src_col,
+            dst_col
+        )
+    }
+}
+
+/// Keeps the colours from `start` to `end`: triggers outside are removed, fades crossing either
+/// edge are cut there, ending or starting with the colour they had reached, and the colour reached
+/// before `start` is set again at `start`.
+fn clip_chroma(triggers: &mut Vec<ChromaTrigger>, start: f32, end: f32) {
+    triggers.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let previous = triggers
+        .iter()
+        .rev()
+        .find(|t| t.time < start)
+        .filter(|t| t.time + t.duration <= start)
+        .map(|t| t.end_color);
+    triggers.retain_mut(|t| {
+        let t_end = t.time + t.duration;
+        if t.time >= end || t_end < start || (t.time < start && t_end == start) {
+            return false;
+        }
+        if t.time < start {
+            t.start_color = t.color_at(start);
+            t.duration = t_end - start;
+            t.time = start;
+        }
+        if t_end > end {
+            t.end_color = t.color_at(end);
+            t.duration = end - t.time;
+        }
+        true
+    });
+    if let Some(color) = previous.filter(|_| start < end) {
+        if triggers.first().is_none_or(|t| t.time > start) {
+            triggers.insert(
+                0,
+                ChromaTrigger {
+                    time: start,
+                    duration: 0.,
+                    start_color: color,
+                    end_color: color,
+                    extra: Map::new(),
+                },
+            );
+        }
+    }
+}
+
 fn chroma_to_text(data: &ChromaTriggersData) -> String {
-    let mut notes = vec![];
-    notes.extend(data.note_a.iter().map(|n| (ChromaNoteType::NoteA, n)));
-    notes.extend(data.note_b.iter().map(|n| (ChromaNoteType::NoteB, n)));
-    notes.extend(data.beat.iter().map(|n| (ChromaNoteType::Beat, n)));
-    notes.extend(data.spin_left.iter().map(|n| (ChromaNoteType::SpinLeft, n)));
-    notes.extend(
-        data.spin_right
-            .iter()
-            .map(|n| (ChromaNoteType::SpinRight, n)),
-    );
-    notes.extend(data.scratch.iter().map(|n| (ChromaNoteType::Scratch, n)));
-    notes.extend(
-        data.ancillary
-            .iter()
-            .map(|n| (ChromaNoteType::Ancillary, n)),
-    );
+    let mut notes: Vec<_> = data.triggers().collect();
     notes.sort_by(|(_, t1), (_, t2)| t1.time.total_cmp(&t2.time));
     notes
         .iter()
         .fold(String::new(), |mut output, (note, trigger)| {
-            let note = note.to_str_chroma();
-            let src_col = RgbColor::from(trigger.start_color).hex();
-            let dst_col = RgbColor::from(trigger.end_color).hex();
-            let str = if trigger.time == 0. && trigger.duration == 0. {
-                format!("Start {} {}", note, src_col)
-            } else if trigger.duration == 0. {
-                format!("Instant {} {:?} {}", note, trigger.time, dst_col)
-            } else {
-                format!(
-                    "{} {:?} {:?} {} {}",
-                    note,
-                    trigger.time,
-                    trigger.time + trigger.duration,
-                    src_col,
-                    dst_col
-                )
-            };
-            let _ = writeln!(output, "{}", str);
+            let _ = writeln!(output, "{}", trigger_to_line(*note, trigger));
             output
         })
 }
 
-pub struct ChromaIntegrator;
+/// Fills in the note type lists older tools left out when they had no triggers.
+fn migrate_v1(payload: &mut Map<String, Value>) {
+    for field in [
+        "NoteA",
+        "NoteB",
+        "Beat",
+        "SpinLeft",
+        "SpinRight",
+        "Scratch",
+        "Ancillary",
+    ] {
+        default_field(payload, field, Value::Array(vec![]));
+    }
+}
 
-impl Integrator for ChromaIntegrator {
-    fn file_extension(&self) -> String {
-        "chroma".into()
+impl Payload for ChromaTriggersData {
+    const SRTB_KEY: &'static str = "SpeenChroma_ChromaTriggers";
+    const MIGRATIONS: &'static [Migration] = &[migrate_v1];
+
+    fn from_lines(lines: &[String], timing: &TimingMap) -> Result<Self, IntegrationError> {
+        lines_to_chroma(lines, timing)
     }
 
-    fn integrate(
-        &self,
-        chart: &mut RawSrtbFile,
-        data: &str,
-        diff: SpinDifficulty,
-    ) -> Result<(), IntegrationError> {
-        let full_data = text_to_chroma(data)?;
-        let key = make_key(diff);
-        let value = serde_json::to_string(&full_data).map_err(IntegrationError::SerdeJsonError)?;
-        chart.set_large_string_value(&key, &value);
-        Ok(())
+    fn to_text(&self, _options: ExtractOptions) -> String {
+        chroma_to_text(self)
     }
 
-    fn extract(
-        &self,
-        chart: &RawSrtbFile,
-        diff: SpinDifficulty,
-    ) -> Result<String, IntegrationError> {
-        let key = make_key(diff);
-        let value = chart
-            .get_large_string_value(&key)
-            .ok_or(IntegrationError::MissingData)?;
-        let data: ChromaTriggersData =
-            serde_json::from_str(&value).map_err(IntegrationError::SerdeJsonError)?;
-        let str = chroma_to_text(&data);
-        Ok(str)
+    fn spans(&self) -> Vec<(f32, f32)> {
+        self.triggers()
+            .map(|(_, t)| (t.time, t.time + t.duration))
+            .collect()
     }
 
-    fn remove(
-        &self,
-        chart: &mut RawSrtbFile,
-        diff: SpinDifficulty,
-    ) -> Result<(), IntegrationError> {
-        let key = make_key(diff);
-        chart.remove_large_string_value(&key);
+    fn changes(old: &Self, new: &Self) -> Vec<TriggerChange> {
+        let mut changes = vec![];
+        for note_type in ChromaNoteType::ALL_NOTES {
+            changes.extend(compare_triggers(
+                Some(note_type),
+                old.note_triggers(note_type),
+                new.note_triggers(note_type),
+                |t| t.time,
+                |t| trigger_to_line(note_type, t),
+            ));
+        }
+        changes.sort_by(|c1, c2| c1.time.total_cmp(&c2.time));
+        changes
+    }
+
+    fn overlay(&mut self, layer: &Self) {
+        for note_type in ChromaNoteType::ALL_NOTES {
+            overlay_triggers(
+                self.note_triggers_mut(note_type),
+                layer.note_triggers(note_type),
+                |t| (t.time, t.time + t.duration),
+            );
+        }
+    }
+
+    fn retime(&mut self, retime: &Retime) -> Result<(), IntegrationError> {
+        for note_type in ChromaNoteType::ALL_NOTES {
+            let triggers = self.note_triggers_mut(note_type);
+            if let Some((start, end)) = retime.window() {
+                clip_chroma(triggers, start, end);
+            }
+            // `Start` triggers set the colour the chart starts with, wherever the others go.
+            for trigger in triggers
+                .iter_mut()
+                .filter(|t| t.time != 0. || t.duration != 0.)
+            {
+                let end = retime.map_time(trigger.time + trigger.duration)?;
+                trigger.time = retime.map_time(trigger.time)?;
+                trigger.duration = end - trigger.time;
+            }
+        }
         Ok(())
     }
+
+    fn sort_triggers(&mut self) {
+        for triggers in [
+            &mut self.note_a,
+            &mut self.note_b,
+            &mut self.beat,
+            &mut self.spin_left,
+            &mut self.spin_right,
+            &mut self.scratch,
+            &mut self.ancillary,
+        ] {
+            triggers.sort_by(|a, b| a.time.total_cmp(&b.time));
+        }
+    }
+}
+
+pub struct ChromaIntegrator;
+
+impl PayloadIntegrator for ChromaIntegrator {
+    type Data = ChromaTriggersData;
+    const FILE_EXTENSION: &'static str = "chroma";
 }
 
 #[cfg(test)]
 mod test {
+    use serde_json::Map;
+
     use crate::{
-        chroma::{chroma_to_text, text_to_chroma, ChromaTrigger, ChromaTriggersData},
+        chroma::{chroma_to_text, ChromaTrigger, ChromaTriggersData},
         color::{HslColor, RgbColor},
-        IntegrationError, ParsingError,
+        payload::compile,
+        track::test::CHART,
+        ChromaIntegrator, IntegrateOptions, IntegrationError, Integrator, ParsingError,
+        PayloadVersion, RawSrtbFile, Retime, SpinDifficulty, TimingMap,
     };
 
+    fn text_to_chroma(
+        content: &str,
+        timing: &TimingMap,
+    ) -> Result<ChromaTriggersData, IntegrationError> {
+        compile(content, timing)
+    }
+
     #[test]
     fn to_chroma() {
         let chroma = r#"
@@ -797,6 +1011,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 0.5,
@@ -811,6 +1026,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 3.,
@@ -825,6 +1041,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 4.,
@@ -839,6 +1056,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
         ];
 
@@ -856,6 +1074,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.,
@@ -870,6 +1089,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 3.,
@@ -884,6 +1104,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 4.,
@@ -898,6 +1119,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
         ];
 
@@ -907,7 +1129,7 @@ mod test {
             ..Default::default()
         };
 
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
 
         println!("Expected: {:#?}", expected_chroma);
         println!("Got: {:#?}", chroma);
@@ -930,6 +1152,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 0.5,
@@ -944,6 +1167,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 3.,
@@ -958,6 +1182,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 4.,
@@ -972,6 +1197,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
         ];
 
@@ -989,6 +1215,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.,
@@ -1003,6 +1230,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 3.,
@@ -1017,6 +1245,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 4.,
@@ -1031,6 +1260,7 @@ mod test {
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
         ];
 
@@ -1076,6 +1306,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 0.5,
@@ -1090,6 +1321,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.,
@@ -1104,6 +1336,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
                     s: 1.,
                     l: 0.5,
                 },
+                extra: Map::new(),
             },
         ];
 
@@ -1112,7 +1345,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
             ..Default::default()
         };
 
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(chroma, expected_chroma);
     }
 
@@ -1134,36 +1367,42 @@ NoteB 4.0 5.0 #ffffff #ff0000
                 duration: 0.5,
                 start_color,
                 end_color,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.5,
                 duration: 0.5,
                 start_color,
                 end_color,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 3.,
                 duration: 0.5,
                 start_color,
                 end_color,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 3.5,
                 duration: 0.5,
                 start_color,
                 end_color,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 5.,
                 duration: 0.5,
                 start_color,
                 end_color,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 5.5,
                 duration: 0.5,
                 start_color,
                 end_color,
+                extra: Map::new(),
             },
         ];
 
@@ -1172,7 +1411,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
             ..Default::default()
         };
 
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(chroma, expected_chroma);
     }
 
@@ -1211,24 +1450,28 @@ NoteB 4.0 5.0 #ffffff #ff0000
                 duration: 0.0,
                 start_color: red,
                 end_color: red,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 2.0,
                 duration: 1.0,
                 start_color: white_blue,
                 end_color: blue,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 4.0,
                 duration: 1.0,
                 start_color: white_red,
                 end_color: red,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 6.0,
                 duration: 1.0,
                 start_color: white_blue,
                 end_color: blue,
+                extra: Map::new(),
             },
         ];
         let note_b = vec![
@@ -1237,24 +1480,28 @@ NoteB 4.0 5.0 #ffffff #ff0000
                 duration: 0.0,
                 start_color: blue,
                 end_color: blue,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 2.0,
                 duration: 1.0,
                 start_color: white_red,
                 end_color: red,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 4.0,
                 duration: 1.0,
                 start_color: white_blue,
                 end_color: blue,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 6.0,
                 duration: 1.0,
                 start_color: white_red,
                 end_color: red,
+                extra: Map::new(),
             },
         ];
         let expected_chroma = ChromaTriggersData {
@@ -1262,7 +1509,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
             note_b,
             ..Default::default()
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         println!("Expected: {:#?}", expected_chroma);
         println!("Got: {:#?}", chroma);
         assert_eq!(chroma, expected_chroma);
@@ -1293,30 +1540,35 @@ NoteB 4.0 5.0 #ffffff #ff0000
                 duration: 0.0,
                 start_color: red,
                 end_color: red,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.0,
                 duration: 0.0,
                 start_color: blue,
                 end_color: blue,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.5,
                 duration: 0.0,
                 start_color: red,
                 end_color: red,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 2.0,
                 duration: 0.0,
                 start_color: blue,
                 end_color: blue,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 2.5,
                 duration: 0.0,
                 start_color: red,
                 end_color: red,
+                extra: Map::new(),
             },
         ];
         let note_b = vec![
@@ -1325,30 +1577,35 @@ NoteB 4.0 5.0 #ffffff #ff0000
                 duration: 0.0,
                 start_color: blue,
                 end_color: blue,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.0,
                 duration: 0.0,
                 start_color: red,
                 end_color: red,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 1.5,
                 duration: 0.0,
                 start_color: blue,
                 end_color: blue,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 2.0,
                 duration: 0.0,
                 start_color: red,
                 end_color: red,
+                extra: Map::new(),
             },
             ChromaTrigger {
                 time: 2.5,
                 duration: 0.0,
                 start_color: blue,
                 end_color: blue,
+                extra: Map::new(),
             },
         ];
         let expected_chroma = ChromaTriggersData {
@@ -1356,7 +1613,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
             note_b,
             ..Default::default()
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
     }
 
@@ -1377,23 +1634,26 @@ NoteB 4.0 5.0 #ffffff #ff0000
                     duration: 0.0,
                     start_color: red,
                     end_color: red,
+                    extra: Map::new(),
                 },
                 ChromaTrigger {
                     time: 1.0,
                     duration: 0.0,
                     start_color: blue,
                     end_color: blue,
+                    extra: Map::new(),
                 },
                 ChromaTrigger {
                     time: 2.0,
                     duration: 0.0,
                     start_color: red,
                     end_color: red,
+                    extra: Map::new(),
                 },
             ],
             ..Default::default()
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
     }
 
@@ -1414,23 +1674,26 @@ NoteB 4.0 5.0 #ffffff #ff0000
                     duration: 0.0,
                     start_color: red,
                     end_color: red,
+                    extra: Map::new(),
                 },
                 ChromaTrigger {
                     time: 1.0,
                     duration: 0.0,
                     start_color: blue,
                     end_color: blue,
+                    extra: Map::new(),
                 },
                 ChromaTrigger {
                     time: 2.0,
                     duration: 1.0,
                     start_color: blue,
                     end_color: red,
+                    extra: Map::new(),
                 },
             ],
             ..Default::default()
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
     }
 
@@ -1452,23 +1715,26 @@ NoteB 4.0 5.0 #ffffff #ff0000
                     duration: 0.0,
                     start_color: red,
                     end_color: red,
+                    extra: Map::new(),
                 },
                 ChromaTrigger {
                     time: 1.0,
                     duration: 0.0,
                     start_color: blue,
                     end_color: blue,
+                    extra: Map::new(),
                 },
                 ChromaTrigger {
                     time: 2.0,
                     duration: 1.0,
                     start_color: green,
                     end_color: red,
+                    extra: Map::new(),
                 },
             ],
             ..Default::default()
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
     }
 
@@ -1482,13 +1748,14 @@ NoteB 4.0 5.0 #ffffff #ff0000
             duration: 0.0,
             start_color: HslColor::from(RgbColor::from_hex(0xff0000)),
             end_color: HslColor::from(RgbColor::from_hex(0xff0000)),
+            extra: Map::new(),
         }];
         let expected_chroma = ChromaTriggersData {
             note_a: tr.clone(),
             note_b: tr.clone(),
             ..Default::default()
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
 
         let chroma = r#"Scratch,SpinLeft,NoteB 0.0 1.0 #ff0000 #00ff00"#;
@@ -1497,6 +1764,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
             duration: 1.0,
             start_color: RgbColor::from_hex(0xff0000).into(),
             end_color: RgbColor::from_hex(0x00ff00).into(),
+            extra: Map::new(),
         }];
         let expected_chroma = ChromaTriggersData {
             note_b: tr.clone(),
@@ -1504,14 +1772,14 @@ NoteB 4.0 5.0 #ffffff #ff0000
             scratch: tr.clone(),
             ..Default::default()
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
     }
 
     #[test]
     fn multinote_all() {
         let chroma = r#"Start All #ffffff"#;
-        let err = text_to_chroma(chroma).unwrap_err();
+        let err = text_to_chroma(chroma, &TimingMap::default()).unwrap_err();
         let IntegrationError::ParsingError(_, err) = err else {
             panic!("invalid error")
         };
@@ -1523,6 +1791,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
             duration: 1.0,
             start_color: HslColor::from(RgbColor::from_hex(0x0)),
             end_color: HslColor::from(RgbColor::from_hex(0xffffff)),
+            extra: Map::new(),
         }];
         let expected_chroma = ChromaTriggersData {
             note_a: tr.clone(),
@@ -1532,8 +1801,9 @@ NoteB 4.0 5.0 #ffffff #ff0000
             spin_right: tr.clone(),
             scratch: tr.clone(),
             ancillary: tr.clone(),
+            extra: Map::new(),
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
 
         let chroma = r#"Instant All 1.0 #000000"#;
@@ -1542,6 +1812,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
             duration: 0.0,
             start_color: HslColor::from(RgbColor::from_hex(0x0)),
             end_color: HslColor::from(RgbColor::from_hex(0x0)),
+            extra: Map::new(),
         }];
         let expected_chroma = ChromaTriggersData {
             note_a: tr.clone(),
@@ -1551,19 +1822,20 @@ NoteB 4.0 5.0 #ffffff #ff0000
             spin_right: tr.clone(),
             scratch: tr.clone(),
             ancillary: tr.clone(),
+            extra: Map::new(),
         };
-        let chroma = text_to_chroma(chroma).unwrap();
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
         assert_eq!(expected_chroma, chroma);
 
         let chroma = r#"Swap Instant 0.5 All All"#;
-        let err = text_to_chroma(chroma).unwrap_err();
+        let err = text_to_chroma(chroma, &TimingMap::default()).unwrap_err();
         let IntegrationError::ParsingError(_, err) = err else {
             panic!("invalid error")
         };
         assert_eq!(err, ParsingError::InvalidNote("all".into()));
 
         let chroma = r#"Swap Flash 0.0 1.0 All All #ffffff"#;
-        let err = text_to_chroma(chroma).unwrap_err();
+        let err = text_to_chroma(chroma, &TimingMap::default()).unwrap_err();
         let IntegrationError::ParsingError(_, err) = err else {
             panic!("invalid error")
         };
@@ -1573,7 +1845,7 @@ NoteB 4.0 5.0 #ffffff #ff0000
     #[test]
     fn multinote_duplicate() {
         let chroma = r#"Instant NoteA,NoteB,NoteA 1.0 #ff0000"#;
-        let err = text_to_chroma(chroma).unwrap_err();
+        let err = text_to_chroma(chroma, &TimingMap::default()).unwrap_err();
         let IntegrationError::ParsingError(_, err) = err else {
             panic!("invalid error");
         };
@@ -1583,10 +1855,187 @@ NoteB 4.0 5.0 #ffffff #ff0000
     #[test]
     fn multinote_invalid_all() {
         let chroma = r#"Instant all, 1.0 #ff0000"#;
-        let err = text_to_chroma(chroma).unwrap_err();
+        let err = text_to_chroma(chroma, &TimingMap::default()).unwrap_err();
         let IntegrationError::ParsingError(_, err) = err else {
             panic!("invalid error");
         };
         assert_eq!(err, ParsingError::InvalidNote("all".into()));
     }
+
+    #[test]
+    fn embedded_source() {
+        let source = "Set red #ff0000\nStart NoteA red\nInstant NoteA 1.0 default\n";
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let options = IntegrateOptions {
+            embed_source: true,
+            ..Default::default()
+        };
+        ChromaIntegrator
+            .integrate_with_options(&mut chart, source, SpinDifficulty::XD, options)
+            .unwrap();
+        assert_eq!(
+            ChromaIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            source
+        );
+
+        ChromaIntegrator
+            .remove(&mut chart, SpinDifficulty::XD)
+            .unwrap();
+        assert!(chart
+            .get_large_string_value("SpeenChroma_ChromaTriggers_XD_Source")
+            .is_none());
+    }
+
+    #[test]
+    fn integrate_layered() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let base = "NoteA 0.0 2.0 #ff0000 #00ff00\nNoteA 4.0 6.0 #00ff00 #0000ff\nInstant NoteB 5.0 #ffffff\n";
+        let normal = "Instant NoteA 5.0 #ffffff\n";
+        ChromaIntegrator
+            .integrate_layered(
+                &mut chart,
+                base,
+                &[(SpinDifficulty::Normal, normal)],
+                PayloadVersion::V1,
+            )
+            .unwrap();
+
+        assert_eq!(
+            ChromaIntegrator
+                .extract(&chart, SpinDifficulty::Normal)
+                .unwrap(),
+            "NoteA 0.0 2.0 #ff0000 #00ff00\nInstant NoteA 5.0 #ffffff\nInstant NoteB 5.0 #ffffff\n"
+        );
+        assert_eq!(
+            ChromaIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            base
+        );
+    }
+
+    #[test]
+    fn migrate_missing_note_types() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        chart.set_large_string_value(
+            "SpeenChroma_ChromaTriggers",
+            r#"{"Beat":[{"Time":1.0,"Duration":0.0,"StartColor":{"H":0.0,"S":0.0,"L":1.0},"EndColor":{"H":0.0,"S":0.0,"L":1.0}}]}"#,
+        );
+        assert_eq!(
+            ChromaIntegrator
+                .extract(&chart, SpinDifficulty::AllDifficulties)
+                .unwrap(),
+            "Instant Beat 1.0 #ffffff\n"
+        );
+    }
+
+    #[test]
+    fn beat_times() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let chroma = "Instant NoteA 4b #ffffff\nNoteB 5:1 5:3 #ff0000 #00ff00\n";
+        ChromaIntegrator
+            .integrate(&mut chart, chroma, SpinDifficulty::XD)
+            .unwrap();
+        assert_eq!(
+            ChromaIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            "Instant NoteA 2.0 #ffffff\nNoteB 8.0 8.5 #ff0000 #00ff00\n"
+        );
+    }
+
+    #[test]
+    fn named_values() {
+        let chroma =
+            "Set red #ff0000\nSet beat 0.5\nMarker drop 2.0\nInstant NoteA drop+beat red\n";
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
+        assert_eq!(chroma.note_a[0].time, 2.5);
+
+        let err = text_to_chroma("Instant NoteA drop #ffffff", &TimingMap::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            IntegrationError::ParsingError(0, ParsingError::ValueNotFound { name, .. }) if name == "drop"
+        ));
+    }
+
+    #[test]
+    fn hex_colors_without_hash() {
+        let chroma = "Set red ff0000
+Set number 123456
+Set time (123456)
+Instant NoteA time red
+Instant NoteB 0 number
+";
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
+        let red = HslColor::from(RgbColor::from_hex(0xff0000));
+        assert_eq!(chroma.note_a[0].time, 123456.);
+        assert_eq!(chroma.note_a[0].start_color, red);
+        assert_eq!(
+            chroma.note_b[0].start_color,
+            HslColor::from(RgbColor::from_hex(0x123456))
+        );
+    }
+
+    #[test]
+    fn retime() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let chroma = "Start NoteA #ff0000\nInstant NoteA 1 #ffffff\nNoteB 2 3 #ff0000 #00ff00\n";
+        ChromaIntegrator
+            .integrate(&mut chart, chroma, SpinDifficulty::XD)
+            .unwrap();
+        let mut retime = |retime| {
+            ChromaIntegrator
+                .retime(&mut chart, SpinDifficulty::XD, &retime)
+                .unwrap();
+            ChromaIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap()
+        };
+        assert_eq!(
+            retime(Retime::Scale {
+                pivot: 1.,
+                factor: 2.
+            }),
+            "Start NoteA #ff0000\nInstant NoteA 1.0 #ffffff\nNoteB 3.0 5.0 #ff0000 #00ff00\n"
+        );
+        assert_eq!(
+            retime(Retime::Shift(1.)),
+            "Start NoteA #ff0000\nInstant NoteA 2.0 #ffffff\nNoteB 4.0 6.0 #ff0000 #00ff00\n"
+        );
+        // Fades crossing the edges are cut there, with the colour they had reached, and the
+        // colour reached before the start is set again there.
+        assert_eq!(
+            retime(Retime::Clip { start: 1., end: 5. }),
+            "Instant NoteA 1.0 #ff0000\nInstant NoteA 2.0 #ffffff\nNoteB 4.0 5.0 #ff0000 #ffff00\n"
+        );
+        assert_eq!(
+            retime(Retime::Clip {
+                start: 4.5,
+                end: 5.
+            }),
+            "Instant NoteA 4.5 #ffffff\nNoteB 4.5 5.0 #ff8000 #ffff00\n"
+        );
+    }
+
+    #[test]
+    fn loop_variables() {
+        let chroma = "Repeat 4 interval 1 as i\nSet c hsl(120, 1, 0.25*i)\nNoteA 0 0.5 #000000 c\nEndRepeat\nInstant NoteB 0 hsl(-60, 2, 0.5)\n";
+        let chroma = text_to_chroma(chroma, &TimingMap::default()).unwrap();
+        let lightness: Vec<_> = chroma.note_a.iter().map(|t| t.end_color.l).collect();
+        assert_eq!(lightness, vec![0., 0.25, 0.5, 0.75]);
+        assert_eq!(chroma.note_a[3].time, 3.);
+        assert_eq!(RgbColor::from(chroma.note_a[2].end_color).hex(), "#00ff00");
+        assert_eq!(
+            RgbColor::from(chroma.note_b[0].start_color).hex(),
+            "#ff00ff"
+        );
+
+        let err = text_to_chroma("Instant NoteA 0 hsl(1, 2)", &TimingMap::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            IntegrationError::ParsingError(0, ParsingError::InvalidHslColor(_))
+        ));
+    }
 }
@@ -0,0 +1,230 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{ChromaNoteType, IntegrationError, IntegratorKind, RawSrtbFile, SpinDifficulty};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    Added(String),
+    Removed(String),
+    Changed { old: String, new: String },
+}
+
+/// A trigger that differs between two charts. Triggers are described the same way they are
+/// extracted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerChange {
+    /// Note type of a chroma trigger, `None` for speed triggers.
+    pub note_type: Option<ChromaNoteType>,
+    pub time: f32,
+    pub kind: ChangeKind,
+}
+
+impl Display for TriggerChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.time)?;
+        if let Some(note_type) = self.note_type {
+            write!(f, " ({})", note_type)?;
+        }
+        match &self.kind {
+            ChangeKind::Added(new) => write!(f, ": added `{}`", new),
+            ChangeKind::Removed(old) => write!(f, ": removed `{}`", old),
+            ChangeKind::Changed { old, new } => write!(f, ": changed `{}` to `{}`", old, new),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DifficultyChanges {
+    pub integrator: IntegratorKind,
+    pub difficulty: SpinDifficulty,
+    pub changes: Vec<TriggerChange>,
+}
+
+/// Compares two lists of triggers. Triggers are matched by time: at a given time, identical
+/// triggers are left out, the remaining ones are reported as changed in order, and any extra ones
+/// as added or removed.
+pub(crate) fn compare_triggers<T: PartialEq>(
+    note_type: Option<ChromaNoteType>,
+    old: &[T],
+    new: &[T],
+    time: impl Fn(&T) -> f32,
+    describe: impl Fn(&T) -> String,
+) -> Vec<TriggerChange> {
+    let mut old: Vec<_> = old.iter().collect();
+    let mut new: Vec<_> = new.iter().collect();
+    old.sort_by(|t1, t2| time(t1).total_cmp(&time(t2)));
+    new.sort_by(|t1, t2| time(t1).total_cmp(&time(t2)));
+
+    let mut changes = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        let current = match (old.get(i), new.get(j)) {
+            (Some(o), Some(n)) => time(o).min(time(n)),
+            (Some(o), None) => time(o),
+            (None, Some(n)) => time(n),
+            (None, None) => unreachable!(),
+        };
+        let mut removed = vec![];
+        while i < old.len() && time(old[i]) == current {
+            removed.push(old[i]);
+            i += 1;
+        }
+        let mut added = vec![];
+        while j < new.len() && time(new[j]) == current {
+            added.push(new[j]);
+            j += 1;
+        }
+        added.retain(|n| match removed.iter().position(|o| o == n) {
+            Some(pos) => {
+                removed.remove(pos);
+                false
+            }
+            None => true,
+        });
+
+        let mut added = added.into_iter();
+        let mut removed = removed.into_iter();
+        loop {
+            let kind = match (removed.next(), added.next()) {
+                (Some(o), Some(n)) => ChangeKind::Changed {
+                    old: describe(o),
+                    new: describe(n),
+                },
+                (Some(o), None) => ChangeKind::Removed(describe(o)),
+                (None, Some(n)) => ChangeKind::Added(describe(n)),
+                (None, None) => break,
+            };
+            changes.push(TriggerChange {
+                note_type,
+                time: current,
+                kind,
+            });
+        }
+    }
+    changes
+}
+
+impl RawSrtbFile {
+    /// Lists the modchart triggers that differ between this chart and `new`, for every
+    /// integrator and difficulty that has differences.
+    pub fn modchart_changes(
+        &self,
+        new: &RawSrtbFile,
+    ) -> Result<Vec<DifficultyChanges>, IntegrationError> {
+        let mut all_changes = vec![];
+        for kind in IntegratorKind::ALL {
+            let integrator = kind.integrator();
+            for diff in SpinDifficulty::ALL {
+                let changes = integrator.changes(self, new, diff)?;
+                if !changes.is_empty() {
+                    all_changes.push(DifficultyChanges {
+                        integrator: kind,
+                        difficulty: diff,
+                        changes,
+                    });
+                }
+            }
+        }
+        Ok(all_changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        track::test::CHART, ChangeKind, ChromaIntegrator, ChromaNoteType, Integrator,
+        IntegratorKind, RawSrtbFile, SpeedsIntegrator, SpinDifficulty, TriggerChange,
+    };
+
+    #[test]
+    fn speeds_changes() {
+        let mut old = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let mut new = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        SpeedsIntegrator
+            .integrate(&mut old, "0 1\n1 2\n2 1\n3 0.5", SpinDifficulty::Normal)
+            .unwrap();
+        SpeedsIntegrator
+            .integrate(
+                &mut new,
+                "0 1\n1 2 true\n3 0.5\n4 1",
+                SpinDifficulty::Normal,
+            )
+            .unwrap();
+
+        let changes = old.modchart_changes(&new).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].integrator, IntegratorKind::Speeds);
+        assert_eq!(changes[0].difficulty, SpinDifficulty::Normal);
+        assert_eq!(
+            changes[0].changes,
+            vec![
+                TriggerChange {
+                    note_type: None,
+                    time: 1.,
+                    kind: ChangeKind::Changed {
+                        old: "1 2 false".into(),
+                        new: "1 2 true".into()
+                    },
+                },
+                TriggerChange {
+                    note_type: None,
+                    time: 2.,
+                    kind: ChangeKind::Removed("2 1 false".into()),
+                },
+                TriggerChange {
+                    note_type: None,
+                    time: 4.,
+                    kind: ChangeKind::Added("4 1 false".into()),
+                },
+            ]
+        );
+        assert_eq!(
+            changes[0].changes[0].to_string(),
+            "1.0: changed `1 2 false` to `1 2 true`"
+        );
+    }
+
+    #[test]
+    fn chroma_changes() {
+        let mut old = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let mut new = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        ChromaIntegrator
+            .integrate(
+                &mut old,
+                "Instant NoteA,NoteB 1.0 #ff0000",
+                SpinDifficulty::AllDifficulties,
+            )
+            .unwrap();
+        ChromaIntegrator
+            .integrate(
+                &mut new,
+                "Instant NoteA 1.0 #ff0000\nInstant NoteB 1.0 #0000ff",
+                SpinDifficulty::AllDifficulties,
+            )
+            .unwrap();
+        SpeedsIntegrator
+            .integrate(&mut new, "0 1", SpinDifficulty::XD)
+            .unwrap();
+
+        let changes = old.modchart_changes(&new).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].integrator, IntegratorKind::Speeds);
+        assert_eq!(
+            changes[0].changes[0].kind,
+            ChangeKind::Added("0 1 false".into())
+        );
+        assert_eq!(changes[1].integrator, IntegratorKind::Chroma);
+        assert_eq!(changes[1].difficulty, SpinDifficulty::AllDifficulties);
+        assert_eq!(
+            changes[1].changes,
+            vec![TriggerChange {
+                note_type: Some(ChromaNoteType::NoteB),
+                time: 1.,
+                kind: ChangeKind::Changed {
+                    old: "Instant NoteB 1.0 #ff0000".into(),
+                    new: "Instant NoteB 1.0 #0000ff".into()
+                },
+            }]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::{f32::consts::PI, str::FromStr};
+
+use crate::ParsingError;
+
+/// Number of segments an eased transition is split into when the script doesn't say.
+pub(crate) const DEFAULT_EASE_STEPS: i32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EasingFamily {
+    Sine,
+    Quad,
+    Cubic,
+    Quart,
+    Quint,
+    Expo,
+    Circ,
+    Back,
+    Elastic,
+    Bounce,
+}
+
+impl EasingFamily {
+    const ALL: [Self; 10] = [
+        Self::Sine,
+        Self::Quad,
+        Self::Cubic,
+        Self::Quart,
+        Self::Quint,
+        Self::Expo,
+        Self::Circ,
+        Self::Back,
+        Self::Elastic,
+        Self::Bounce,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sine => "sine",
+            Self::Quad => "quad",
+            Self::Cubic => "cubic",
+            Self::Quart => "quart",
+            Self::Quint => "quint",
+            Self::Expo => "expo",
+            Self::Circ => "circ",
+            Self::Back => "back",
+            Self::Elastic => "elastic",
+            Self::Bounce => "bounce",
+        }
+    }
+
+    /// The "in" variant of the curve, going from 0 at `x = 0` to 1 at `x = 1`.
+    fn ease_in(self, x: f32) -> f32 {
+        match self {
+            Self::Sine => 1. - (x * PI / 2.).cos(),
+            Self::Quad => x.powi(2),
+            Self::Cubic => x.powi(3),
+            Self::Quart => x.powi(4),
+            Self::Quint => x.powi(5),
+            Self::Expo if x == 0. => 0.,
+            Self::Expo => 2f32.powf(10. * x - 10.),
+            Self::Circ => 1. - (1. - x.powi(2)).sqrt(),
+            Self::Back => {
+                const C1: f32 = 1.70158;
+                (C1 + 1.) * x.powi(3) - C1 * x.powi(2)
+            }
+            Self::Elastic if x == 0. || x == 1. => x,
+            Self::Elastic => {
+                -(2f32.powf(10. * x - 10.)) * ((x * 10. - 10.75) * (2. * PI / 3.)).sin()
+            }
+            Self::Bounce => 1. - bounce_out(1. - x),
+        }
+    }
+}
+
+fn bounce_out(x: f32) -> f32 {
+    const N1: f32 = 7.5625;
+    const D1: f32 = 2.75;
+    if x < 1. / D1 {
+        N1 * x * x
+    } else if x < 2. / D1 {
+        let x = x - 1.5 / D1;
+        N1 * x * x + 0.75
+    } else if x < 2.5 / D1 {
+        let x = x - 2.25 / D1;
+        N1 * x * x + 0.9375
+    } else {
+        let x = x - 2.625 / D1;
+        N1 * x * x + 0.984375
+    }
+}
+
+/// A transition curve, written `linear` or `in`, `out` or `inOut` followed by a family such as
+/// `Cubic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Easing {
+    Linear,
+    In(EasingFamily),
+    Out(EasingFamily),
+    InOut(EasingFamily),
+}
+
+impl Easing {
+    /// Progress of the transition at `x`, both going from 0 to 1.
+    pub(crate) fn apply(self, x: f32) -> f32 {
+        match self {
+            Self::Linear => x,
+            Self::In(family) => family.ease_in(x),
+            Self::Out(family) => 1. - family.ease_in(1. - x),
+            Self::InOut(family) if x < 0.5 => family.ease_in(2. * x) / 2.,
+            Self::InOut(family) => 1. - family.ease_in(2. - 2. * x) / 2.,
+        }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = ParsingError;
+
+    fn from_str(easing: &str) -> Result<Self, Self::Err> {
+        let lowercase = easing.to_lowercase();
+        if lowercase == "linear" {
+            return Ok(Self::Linear);
+        }
+        let (variant, family): (fn(EasingFamily) -> Self, _) =
+            if let Some(family) = lowercase.strip_prefix("inout") {
+                (Self::InOut, family)
+            } else if let Some(family) = lowercase.strip_prefix("in") {
+                (Self::In, family)
+            } else if let Some(family) = lowercase.strip_prefix("out") {
+                (Self::Out, family)
+            } else {
+                return Err(ParsingError::InvalidEasing(easing.into()));
+            };
+        EasingFamily::ALL
+            .into_iter()
+            .find(|f| f.name() == family)
+            .map(variant)
+            .ok_or(ParsingError::InvalidEasing(easing.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{easing::Easing, ParsingError};
+
+    #[test]
+    fn curves() {
+        for name in [
+            "linear",
+            "inSine",
+            "outQuad",
+            "inOutCubic",
+            "INOUTBOUNCE",
+            "inElastic",
+        ] {
+            let easing: Easing = name.parse().unwrap();
+            assert_eq!(easing.apply(0.), 0., "{}", name);
+            assert!((easing.apply(1.) - 1.).abs() < 1e-6, "{}", name);
+        }
+        let cubic: Easing = "inOutCubic".parse().unwrap();
+        assert_eq!(cubic.apply(0.25), 0.0625);
+        assert_eq!(cubic.apply(0.75), 0.9375);
+        assert!("outBack".parse::<Easing>().unwrap().apply(0.5) > 1.);
+
+        assert_eq!(
+            "inWobble".parse::<Easing>(),
+            Err(ParsingError::InvalidEasing("inWobble".into()))
+        );
+    }
+}
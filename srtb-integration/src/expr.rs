@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+
+use crate::ParsingError;
+
+/// Named values expressions can refer to, on top of the built-in constants.
+pub(crate) type Names = HashMap<String, f32>;
+
+const CONSTANTS: [(&str, f32); 3] = [
+    ("pi", std::f32::consts::PI),
+    ("tau", std::f32::consts::TAU),
+    ("e", std::f32::consts::E),
+];
+
+const FUNCTIONS: [&str; 2] = ["min", "max"];
+
+/// Script commands, which a value name would be confused with at the start of a line.
+const RESERVED: [&str; 16] = [
+    "hold",
+    "pulse",
+    "freeze",
+    "ease",
+    "wobble",
+    "repeat",
+    "endrepeat",
+    "set",
+    "marker",
+    "start",
+    "instant",
+    "swap",
+    "undo",
+    "flash",
+    "fade",
+    "include",
+];
+
+/// Checks that `name` can be given to a value: it must look like an identifier and not hide a
+/// built-in constant or function or a command.
+pub(crate) fn check_name(name: &str) -> Result<(), ParsingError> {
+    let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !CONSTANTS.iter().any(|(constant, _)| *constant == name)
+        && !FUNCTIONS.contains(&name)
+        && !RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(name));
+    if valid {
+        Ok(())
+    } else {
+        Err(ParsingError::InvalidValueName(name.into()))
+    }
+}
+
+/// Splits a script line into arguments at whitespace, keeping parenthesized expressions such as
+/// `(1 + 2)` together.
+pub(crate) fn split_arguments(line: &str) -> Vec<&str> {
+    let mut arguments = vec![];
+    let mut depth = 0;
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth <= 0 => {
+                if let Some(start) = start.take() {
+                    arguments.push(&line[start..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(start) = start {
+        arguments.push(&line[start..]);
+    }
+    arguments
+}
+
+/// Evaluates an arithmetic expression: numbers, `+ - * /`, parentheses, `min(..)` and `max(..)`,
+/// the constants `pi`, `tau` and `e`, and the values in `names`.
+pub(crate) fn eval(expression: &str, names: &Names) -> Result<f32, ParsingError> {
+    if let Ok(value) = expression.parse() {
+        return Ok(value);
+    }
+    let mut parser = Parser {
+        expression,
+        names,
+        pos: 0,
+    };
+    let value = parser.sum()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(parser.error(format!("unexpected `{}`", c))),
+    }
+}
+
+/// Evaluates an expression that must give a whole number.
+pub(crate) fn eval_int(expression: &str, names: &Names) -> Result<i32, ParsingError> {
+    if let Ok(value) = expression.parse() {
+        return Ok(value);
+    }
+    let value = eval(expression, names)?;
+    if value.fract() != 0. || value.abs() > i32::MAX as f32 {
+        return Err(ParsingError::InvalidInt(expression.into()));
+    }
+    Ok(value as i32)
+}
+
+/// Evaluates comma-separated expressions.
+pub(crate) fn eval_list(expressions: &str, names: &Names) -> Result<Vec<f32>, ParsingError> {
+    let mut parser = Parser {
+        expression: expressions,
+        names,
+        pos: 0,
+    };
+    let mut values = vec![parser.sum()?];
+    while parser.eat(',') {
+        values.push(parser.sum()?);
+    }
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(values),
+        Some(c) => Err(parser.error(format!("unexpected `{}`", c))),
+    }
+}
+
+/// The index of a `Repeat X interval Y as name` block, counting iterations from 0. The name is
+/// only defined inside the block.
+pub(crate) struct LoopVariable {
+    name: String,
+    /// Value the name had before the block.
+    shadowed: Option<f32>,
+}
+
+impl LoopVariable {
+    /// Parses what follows the interval of a `Repeat` line and defines the variable, if any. Other
+    /// trailing arguments are ignored, as they were before loop variables existed.
+    pub(crate) fn parse(
+        arguments: &[&str],
+        names: &mut Names,
+    ) -> Result<Option<Self>, ParsingError> {
+        match arguments {
+            ["as"] => Err(ParsingError::InvalidRepeatCommand),
+            ["as", name, ..] => {
+                check_name(name)?;
+                Ok(Some(Self {
+                    name: name.to_string(),
+                    shadowed: names.insert(name.to_string(), 0.),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn set(&self, iteration: i32, names: &mut Names) {
+        names.insert(self.name.clone(), iteration as f32);
+    }
+
+    /// Gives the name back its value from before the block.
+    pub(crate) fn end(self, names: &mut Names) {
+        match self.shadowed {
+            Some(value) => names.insert(self.name, value),
+            None => names.remove(&self.name),
+        };
+    }
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    names: &'a Names,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.expression[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Length of the run of characters matching `pred` at the current position.
+    fn token_len(&self, pred: fn(char) -> bool) -> usize {
+        let rest = self.rest();
+        rest.find(|c: char| !pred(c)).unwrap_or(rest.len())
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `c` if it is the next non-whitespace character.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, message: String) -> ParsingError {
+        ParsingError::InvalidExpression {
+            expression: self.expression.into(),
+            column: self.expression[..self.pos].chars().count() + 1,
+            message,
+        }
+    }
+
+    fn unexpected(&self) -> ParsingError {
+        match self.peek() {
+            Some(c) => self.error(format!("unexpected `{}`", c)),
+            None => self.error("unexpected end of expression".into()),
+        }
+    }
+
+    fn sum(&mut self) -> Result<f32, ParsingError> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat('+') {
+                value += self.product()?;
+            } else if self.eat('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<f32, ParsingError> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                value /= self.unary()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<f32, ParsingError> {
+        if self.eat('-') {
+            Ok(-self.unary()?)
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<f32, ParsingError> {
+        self.skip_whitespace();
+        if self.eat('(') {
+            let value = self.sum()?;
+            if !self.eat(')') {
+                return Err(self.unexpected());
+            }
+            return Ok(value);
+        }
+
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                self.pos += self.token_len(|c| c.is_ascii_digit() || c == '.');
+                self.exponent();
+                let number = &self.expression[start..self.pos];
+                match number.parse() {
+                    Ok(value) => Ok(value),
+                    Err(_) => {
+                        self.pos = start;
+                        Err(self.error(format!("invalid number `{}`", number)))
+                    }
+                }
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                self.pos += self.token_len(|c| c.is_alphanumeric() || c == '_');
+                let name = &self.expression[start..self.pos];
+                if self.eat('(') {
+                    return self.call(name, start);
+                }
+                if let Some(value) = self.names.get(name) {
+                    return Ok(*value);
+                }
+                if let Some((_, value)) = CONSTANTS.iter().find(|(constant, _)| *constant == name) {
+                    return Ok(*value);
+                }
+                Err(ParsingError::ValueNotFound {
+                    name: name.into(),
+                    column: self.expression[..start].chars().count() + 1,
+                })
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Consumes the exponent of a number such as `1e3` or `2.5E-2`, if there is one.
+    fn exponent(&mut self) {
+        let rest = self.rest();
+        let Some(exponent) = rest.strip_prefix(['e', 'E']) else {
+            return;
+        };
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            let digits_len = digits.len()
+                - digits
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .len();
+            self.pos += rest.len() - digits.len() + digits_len;
+        }
+    }
+
+    /// Evaluates the arguments of a function call, the opening parenthesis being consumed.
+    fn call(&mut self, name: &str, start: usize) -> Result<f32, ParsingError> {
+        let mut arguments = vec![self.sum()?];
+        while self.eat(',') {
+            arguments.push(self.sum()?);
+        }
+        if !self.eat(')') {
+            return Err(self.unexpected());
+        }
+        let reduce = match name {
+            "min" => f32::min,
+            "max" => f32::max,
+            _ => {
+                self.pos = start;
+                return Err(self.error(format!("unknown function `{}`", name)));
+            }
+        };
+        Ok(arguments.into_iter().reduce(reduce).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        expr::{check_name, eval, eval_int, eval_list, split_arguments, Names},
+        ParsingError,
+    };
+
+    #[test]
+    fn evaluate() {
+        let names = Names::from([("drop".to_string(), 32.)]);
+        let eval = |expression| eval(expression, &names).unwrap();
+        assert_eq!(eval("1.5"), 1.5);
+        assert_eq!(eval("1+2*3"), 7.);
+        assert_eq!(eval("(1 + 2) * 3"), 9.);
+        assert_eq!(eval("-2--3"), 1.);
+        assert_eq!(eval("drop+3/8"), 32.375);
+        assert_eq!(eval("max(1, drop, 4) - min(2,3)"), 30.);
+        assert_eq!(eval("2*pi"), std::f32::consts::TAU);
+        assert_eq!(eval("1e3+1"), 1001.);
+        assert_eq!(eval("2.5E-1*4"), 1.);
+        assert_eq!(eval("2*e"), 2. * std::f32::consts::E);
+        assert_eq!(
+            eval_list("1, max(2, 3), drop/4", &names),
+            Ok(vec![1., 3., 8.])
+        );
+        assert_eq!(eval_int("4*2", &names), Ok(8));
+        assert_eq!(
+            eval_int("3/2", &names),
+            Err(ParsingError::InvalidInt("3/2".into()))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let names = Names::new();
+        let error = |expression, column, message: &str| {
+            assert_eq!(
+                eval(expression, &names),
+                Err(ParsingError::InvalidExpression {
+                    expression: String::from(expression),
+                    column,
+                    message: message.into(),
+                })
+            );
+        };
+        error("1+*2", 3, "unexpected `*`");
+        error("(1+2", 5, "unexpected end of expression");
+        error("1.2.3+1", 1, "invalid number `1.2.3`");
+        error("2 3", 3, "unexpected `3`");
+        error("abs(1)", 1, "unknown function `abs`");
+        error("2e", 2, "unexpected `e`");
+        assert_eq!(
+            eval("2 * foo", &names),
+            Err(ParsingError::ValueNotFound {
+                name: "foo".into(),
+                column: 5
+            })
+        );
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            split_arguments("  1 (2 + 3)  max(1, 2)b #fff"),
+            vec!["1", "(2 + 3)", "max(1, 2)b", "#fff"]
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(check_name("drop_2"), Ok(()));
+        for name in ["2x", "a-b", "pi", "max", "", "pulse", "Freeze", "ENDREPEAT"] {
+            assert_eq!(
+                check_name(name),
+                Err(ParsingError::InvalidValueName(name.into()))
+            );
+        }
+    }
+}
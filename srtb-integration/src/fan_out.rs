@@ -0,0 +1,215 @@
+use crate::{source_key, IntegrationError, IntegratorKind, RawSrtbFile, SpinDifficulty};
+
+/// Which key a mod reads for a difficulty. Mods look for the difficulty's own key first and fall
+/// back to the shared [`SpinDifficulty::AllDifficulties`] key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsedKey {
+    /// The difficulty's own key. `shadows_shared` is set when the shared key also exists and is
+    /// therefore ignored for this difficulty.
+    Own {
+        shadows_shared: bool,
+    },
+    Shared,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub difficulty: SpinDifficulty,
+    /// `None` if the difficulty has no data at all.
+    pub used: Option<UsedKey>,
+}
+
+impl RawSrtbFile {
+    /// Reports which key the mod will use for every difficulty of the chart.
+    pub fn key_usage(&self, kind: IntegratorKind) -> Result<Vec<KeyUsage>, IntegrationError> {
+        let integrator = kind.integrator();
+        let shared = self
+            .get_large_string_value(&integrator.srtb_key(SpinDifficulty::AllDifficulties))
+            .is_some();
+        Ok(self
+            .difficulties()?
+            .into_iter()
+            .map(|diff| {
+                let own = self
+                    .get_large_string_value(&integrator.srtb_key(diff))
+                    .is_some();
+                let used = match (own, shared) {
+                    (true, _) => Some(UsedKey::Own {
+                        shadows_shared: shared,
+                    }),
+                    (false, true) => Some(UsedKey::Shared),
+                    (false, false) => None,
+                };
+                KeyUsage {
+                    difficulty: diff,
+                    used,
+                }
+            })
+            .collect())
+    }
+
+    /// Copies the shared data into the key of every difficulty that relies on it, then removes
+    /// the shared key. Difficulties that already have their own data keep it, so what the mod
+    /// does is unchanged. Returns the difficulties that received a copy.
+    pub fn fan_out(
+        &mut self,
+        kind: IntegratorKind,
+    ) -> Result<Vec<SpinDifficulty>, IntegrationError> {
+        let integrator = kind.integrator();
+        let shared_key = integrator.srtb_key(SpinDifficulty::AllDifficulties);
+        let Some(shared) = self.get_large_string_value(&shared_key).map(String::from) else {
+            return Ok(vec![]);
+        };
+        let shared_source = self
+            .get_large_string_value(&source_key(&shared_key))
+            .map(String::from);
+
+        let mut expanded = vec![];
+        for usage in self.key_usage(kind)? {
+            if usage.used != Some(UsedKey::Shared) {
+                continue;
+            }
+            let key = integrator.srtb_key(usage.difficulty);
+            self.set_large_string_value(&key, &shared);
+            if let Some(source) = &shared_source {
+                self.set_large_string_value(&source_key(&key), source);
+            }
+            expanded.push(usage.difficulty);
+        }
+        self.remove_large_string_value(&shared_key);
+        self.remove_large_string_value(&source_key(&shared_key));
+        Ok(expanded)
+    }
+
+    /// Moves the data into the shared key if every difficulty of the chart ends up using the
+    /// exact same data, removing the per-difficulty keys. Returns whether anything was merged.
+    pub fn consolidate(&mut self, kind: IntegratorKind) -> Result<bool, IntegrationError> {
+        let integrator = kind.integrator();
+        let shared_key = integrator.srtb_key(SpinDifficulty::AllDifficulties);
+        let usages = self.key_usage(kind)?;
+        if !usages
+            .iter()
+            .any(|usage| matches!(usage.used, Some(UsedKey::Own { .. })))
+        {
+            return Ok(false);
+        }
+
+        // The data and source script each difficulty ends up with.
+        let mut effective = vec![];
+        for usage in &usages {
+            let key = match usage.used {
+                Some(UsedKey::Own { .. }) => integrator.srtb_key(usage.difficulty),
+                Some(UsedKey::Shared) => shared_key.clone(),
+                None => return Ok(false),
+            };
+            effective.push((
+                self.get_large_string_value(&key),
+                self.get_large_string_value(&source_key(&key)),
+            ));
+        }
+        let (data, source) = effective[0];
+        if effective.iter().any(|(d, _)| *d != data) {
+            return Ok(false);
+        }
+        let data = data.unwrap_or_default().to_string();
+        let source = effective
+            .iter()
+            .all(|(_, s)| *s == source)
+            .then(|| source.map(String::from))
+            .flatten();
+
+        for usage in &usages {
+            let key = integrator.srtb_key(usage.difficulty);
+            self.remove_large_string_value(&key);
+            self.remove_large_string_value(&source_key(&key));
+        }
+        self.set_large_string_value(&shared_key, &data);
+        match source {
+            Some(source) => self.set_large_string_value(&source_key(&shared_key), &source),
+            None => self.remove_large_string_value(&source_key(&shared_key)),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        track::test::CHART, IntegrateOptions, Integrator, IntegratorKind, KeyUsage, RawSrtbFile,
+        SpeedsIntegrator, SpinDifficulty, UsedKey,
+    };
+
+    #[test]
+    fn report_key_usage() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        assert_eq!(
+            chart.key_usage(IntegratorKind::Speeds).unwrap(),
+            vec![
+                KeyUsage {
+                    difficulty: SpinDifficulty::Normal,
+                    used: None
+                },
+                KeyUsage {
+                    difficulty: SpinDifficulty::XD,
+                    used: None
+                },
+            ]
+        );
+
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 1", SpinDifficulty::AllDifficulties)
+            .unwrap();
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 2", SpinDifficulty::XD)
+            .unwrap();
+        let usage = chart.key_usage(IntegratorKind::Speeds).unwrap();
+        assert_eq!(usage[0].used, Some(UsedKey::Shared));
+        assert_eq!(
+            usage[1].used,
+            Some(UsedKey::Own {
+                shadows_shared: true
+            })
+        );
+    }
+
+    #[test]
+    fn fan_out_and_consolidate() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let options = IntegrateOptions {
+            embed_source: true,
+            ..Default::default()
+        };
+        SpeedsIntegrator
+            .integrate_with_options(&mut chart, "0 1", SpinDifficulty::AllDifficulties, options)
+            .unwrap();
+
+        assert!(!chart.consolidate(IntegratorKind::Speeds).unwrap());
+        assert_eq!(
+            chart.fan_out(IntegratorKind::Speeds).unwrap(),
+            vec![SpinDifficulty::Normal, SpinDifficulty::XD]
+        );
+        assert!(chart
+            .get_large_string_value("SpeedHelper_SpeedTriggers")
+            .is_none());
+        for diff in [SpinDifficulty::Normal, SpinDifficulty::XD] {
+            assert_eq!(SpeedsIntegrator.extract(&chart, diff).unwrap(), "0 1");
+        }
+
+        assert!(chart.consolidate(IntegratorKind::Speeds).unwrap());
+        assert!(chart
+            .get_large_string_value("SpeedHelper_SpeedTriggers_NORMAL")
+            .is_none());
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::AllDifficulties)
+                .unwrap(),
+            "0 1"
+        );
+
+        chart.fan_out(IntegratorKind::Speeds).unwrap();
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 2", SpinDifficulty::XD)
+            .unwrap();
+        assert!(!chart.consolidate(IntegratorKind::Speeds).unwrap());
+    }
+}
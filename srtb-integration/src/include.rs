@@ -0,0 +1,274 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{IntegrationError, ParsingError};
+
+/// Reads the scripts pulled in by `Include "path"` directives.
+pub trait ScriptLoader {
+    fn load(&self, path: &Path) -> std::io::Result<String>;
+}
+
+/// Reads included scripts from disk.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileLoader;
+
+impl ScriptLoader for FileLoader {
+    fn load(&self, path: &Path) -> std::io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// In-memory scripts by path, for when there is no filesystem to read from.
+impl ScriptLoader for HashMap<PathBuf, String> {
+    fn load(&self, path: &Path) -> std::io::Result<String> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+}
+
+/// Where the script being integrated lives, so that its `Include` directives can be resolved.
+#[derive(Clone, Copy)]
+pub struct IncludeContext<'a> {
+    /// Path of the script itself. Included paths are relative to its directory.
+    pub path: &'a Path,
+    pub loader: &'a dyn ScriptLoader,
+}
+
+/// A script with its `Include` directives replaced by the lines of the included scripts.
+#[derive(Debug, Default)]
+pub(crate) struct Script {
+    lines: Vec<String>,
+    /// Paths of the included scripts.
+    files: Vec<PathBuf>,
+    /// Included script (`None` for the script itself) and line number every line comes from.
+    origins: Vec<(Option<usize>, usize)>,
+    /// Number of lines of the script itself.
+    line_count: usize,
+}
+
+impl Script {
+    /// Expands the `Include` directives of `text`. Without `includes`, they are an error.
+    pub(crate) fn expand(
+        text: &str,
+        includes: Option<&IncludeContext>,
+    ) -> Result<Self, IntegrationError> {
+        let mut script = Self {
+            line_count: text.lines().count(),
+            ..Default::default()
+        };
+        let mut stack: Vec<_> = includes.map(|i| normalize(i.path)).into_iter().collect();
+        script.append(text, None, includes, &mut stack)?;
+        Ok(script)
+    }
+
+    /// Appends the lines of `text`, coming from `file`, expanding the scripts it includes.
+    /// `stack` holds the paths of the scripts being expanded, `text` being the last one.
+    fn append(
+        &mut self,
+        text: &str,
+        file: Option<usize>,
+        includes: Option<&IncludeContext>,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<(), IntegrationError> {
+        for (line_number, line) in text.lines().enumerate() {
+            let Some(path) = include_path(line) else {
+                self.lines.push(line.to_string());
+                self.origins.push((file, line_number));
+                continue;
+            };
+            let (path, includes) = match (path, includes, stack.last()) {
+                (Ok(path), Some(includes), Some(current)) => {
+                    let directory = current.parent().unwrap_or(Path::new(""));
+                    (normalize(&directory.join(path)), includes)
+                }
+                (Err(e), ..) => return Err(self.error_at(file, line_number, e)),
+                _ => {
+                    return Err(self.error_at(file, line_number, ParsingError::NoScriptLoader));
+                }
+            };
+            if stack.contains(&path) {
+                let error = ParsingError::IncludeCycle(path.display().to_string());
+                return Err(self.error_at(file, line_number, error));
+            }
+            let content = includes.loader.load(&path).map_err(|e| {
+                let error = ParsingError::IncludeFailed(path.display().to_string(), e.to_string());
+                self.error_at(file, line_number, error)
+            })?;
+            self.files.push(path.clone());
+            stack.push(path);
+            self.append(&content, Some(self.files.len() - 1), Some(includes), stack)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// The source to embed in the chart for `text`, this script before expansion: included
+    /// scripts are inlined so that the source compiles on its own.
+    pub(crate) fn embedded_source<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.files.is_empty() {
+            Cow::Borrowed(text)
+        } else {
+            Cow::Owned(
+                self.lines
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect(),
+            )
+        }
+    }
+
+    fn error_at(&self, file: Option<usize>, line: usize, error: ParsingError) -> IntegrationError {
+        match file {
+            None => IntegrationError::ParsingError(line, error),
+            Some(file) => IntegrationError::IncludedParsingError {
+                file: self.files[file].display().to_string(),
+                line,
+                error,
+            },
+        }
+    }
+
+    /// Turns an error on a line of the expanded script into one on the line it comes from.
+    pub(crate) fn locate(&self, error: IntegrationError) -> IntegrationError {
+        match error {
+            IntegrationError::ParsingError(index, error) => match self.origins.get(index) {
+                Some(&(file, line)) => self.error_at(file, line, error),
+                None => IntegrationError::ParsingError(self.line_count, error),
+            },
+            error => error,
+        }
+    }
+}
+
+/// The path of an `Include "path"` line, `None` for other lines.
+fn include_path(line: &str) -> Option<Result<&str, ParsingError>> {
+    let line = line.trim();
+    let (command, path) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if !command.eq_ignore_ascii_case("include") {
+        return None;
+    }
+    let path = path.trim();
+    Some(
+        path.strip_prefix('"')
+            .and_then(|path| path.strip_suffix('"'))
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| ParsingError::InvalidInclude(path.into())),
+    )
+}
+
+/// Resolves `.` and `..` components without touching the filesystem, so that a script is always
+/// reached through the same path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    use crate::{
+        include::{IncludeContext, Script},
+        IntegrationError, ParsingError,
+    };
+
+    fn files() -> HashMap<PathBuf, String> {
+        HashMap::from([
+            (
+                "lib/intro.txt".into(),
+                "a\nInclude \"../lib/b.txt\"\n".into(),
+            ),
+            ("lib/b.txt".into(), "b1\nb2\n".into()),
+            ("lib/loop.txt".into(), "include \"./loop2.txt\"".into()),
+            ("lib/loop2.txt".into(), "x\nINCLUDE \"loop.txt\"".into()),
+        ])
+    }
+
+    #[test]
+    fn expand() {
+        let files = files();
+        let includes = IncludeContext {
+            path: Path::new("main.txt"),
+            loader: &files,
+        };
+        let text = "start\n  Include \"lib/intro.txt\"  \nend";
+        let script = Script::expand(text, Some(&includes)).unwrap();
+        assert_eq!(script.lines(), ["start", "a", "b1", "b2", "end"]);
+        assert_eq!(script.embedded_source(text), "start\na\nb1\nb2\nend\n");
+        assert_eq!(
+            script
+                .locate(IntegrationError::ParsingError(
+                    3,
+                    ParsingError::MissingArguments
+                ))
+                .to_string(),
+            "parsing error in lib/b.txt on line 1: not enough arguments"
+        );
+        assert!(matches!(
+            script.locate(IntegrationError::ParsingError(
+                4,
+                ParsingError::MissingArguments
+            )),
+            IntegrationError::ParsingError(2, _)
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let files = files();
+        let includes = IncludeContext {
+            path: Path::new("main.txt"),
+            loader: &files,
+        };
+        let error = |text| Script::expand(text, Some(&includes)).unwrap_err();
+        assert!(matches!(
+            error("x\nInclude \"lib/loop.txt\""),
+            IntegrationError::IncludedParsingError {
+                file,
+                line: 1,
+                error: ParsingError::IncludeCycle(cycle),
+            } if file == "lib/loop2.txt" && cycle == "lib/loop.txt"
+        ));
+        assert!(matches!(
+            error("Include \"main.txt\""),
+            IntegrationError::ParsingError(0, ParsingError::IncludeCycle(_))
+        ));
+        assert!(matches!(
+            error("Include \"missing.txt\""),
+            IntegrationError::ParsingError(0, ParsingError::IncludeFailed(path, _)) if path == "missing.txt"
+        ));
+        assert!(matches!(
+            error("Include lib/b.txt"),
+            IntegrationError::ParsingError(0, ParsingError::InvalidInclude(_))
+        ));
+        assert!(matches!(
+            Script::expand("Include \"lib/b.txt\"", None).unwrap_err(),
+            IntegrationError::ParsingError(0, ParsingError::NoScriptLoader)
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{source_key, IntegratorKind, RawSrtbFile, SpinDifficulty};
+
+/// Overview of the data an integrator stored for one difficulty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntegrationSummary {
+    pub trigger_count: usize,
+    /// Start of the first trigger and end of the last one, in seconds. `None` if there are no
+    /// triggers.
+    pub time_range: Option<(f32, f32)>,
+    /// Size of the stored payload, in bytes.
+    pub payload_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryEntry {
+    pub integrator: IntegratorKind,
+    pub difficulty: SpinDifficulty,
+    /// Why the data couldn't be summarized if it is malformed.
+    pub summary: Result<IntegrationSummary, String>,
+}
+
+/// All the modchart data found in a chart.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Inventory {
+    pub entries: Vec<InventoryEntry>,
+    /// Large string keys that are neither referenced by the chart itself nor produced by an
+    /// integrator, most likely data from other mods.
+    pub unknown_keys: Vec<String>,
+}
+
+impl RawSrtbFile {
+    pub fn inventory(&self) -> Inventory {
+        let mut inventory = Inventory::default();
+        let mut known_keys: Vec<_> = self.json_keys().map(String::from).collect();
+        for kind in IntegratorKind::ALL {
+            let integrator = kind.integrator();
+            for diff in SpinDifficulty::ALL {
+                let key = integrator.srtb_key(diff);
+                if self.get_large_string_value(&key).is_some() {
+                    inventory.entries.push(InventoryEntry {
+                        integrator: kind,
+                        difficulty: diff,
+                        summary: integrator.summarize(self, diff).map_err(|e| e.to_string()),
+                    });
+                }
+                known_keys.push(source_key(&key));
+                known_keys.push(key);
+            }
+        }
+        inventory.unknown_keys = self
+            .large_string_keys()
+            .filter(|key| !known_keys.iter().any(|k| k == key))
+            .map(String::from)
+            .collect();
+        inventory
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        track::test::CHART, ChromaIntegrator, IntegrationSummary, Integrator, IntegratorKind,
+        RawSrtbFile, SpeedsIntegrator, SpinDifficulty,
+    };
+
+    #[test]
+    fn list_integrated_data() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let inventory = chart.inventory();
+        assert!(inventory.entries.is_empty());
+        assert!(inventory.unknown_keys.is_empty());
+
+        SpeedsIntegrator
+            .integrate(&mut chart, "1 1\n4.5 2 true", SpinDifficulty::Normal)
+            .unwrap();
+        ChromaIntegrator
+            .integrate(
+                &mut chart,
+                "Start NoteA #ff0000\nNoteA,NoteB 2.0 3.5 #ff0000 #0000ff",
+                SpinDifficulty::AllDifficulties,
+            )
+            .unwrap();
+        chart.set_large_string_value("SpeedHelper_SpeedTriggers_BONUS", "{}");
+        chart.set_large_string_value("OtherMod_Data", "data");
+
+        let inventory = chart.inventory();
+        assert_eq!(inventory.entries.len(), 2);
+
+        let speeds = &inventory.entries[0];
+        assert_eq!(speeds.integrator, IntegratorKind::Speeds);
+        assert_eq!(speeds.difficulty, SpinDifficulty::Normal);
+        assert_eq!(
+            speeds.summary,
+            Ok(IntegrationSummary {
+                trigger_count: 2,
+                time_range: Some((1., 4.5)),
+                payload_size: chart
+                    .get_large_string_value("SpeedHelper_SpeedTriggers_NORMAL")
+                    .unwrap()
+                    .len(),
+            })
+        );
+
+        let chroma = &inventory.entries[1];
+        assert_eq!(chroma.integrator, IntegratorKind::Chroma);
+        assert_eq!(chroma.difficulty, SpinDifficulty::AllDifficulties);
+        let summary = chroma.summary.as_ref().unwrap();
+        assert_eq!(summary.trigger_count, 3);
+        assert_eq!(summary.time_range, Some((0., 3.5)));
+
+        assert_eq!(
+            inventory.unknown_keys,
+            vec!["SpeedHelper_SpeedTriggers_BONUS", "OtherMod_Data"]
+        );
+    }
+
+    #[test]
+    fn malformed_entry() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        chart.set_large_string_value("SpeedHelper_SpeedTriggers_XD", "not json");
+        SpeedsIntegrator
+            .integrate(&mut chart, "1 1", SpinDifficulty::Normal)
+            .unwrap();
+        let inventory = chart.inventory();
+        assert_eq!(inventory.entries.len(), 2);
+        assert!(inventory.entries[0].summary.is_ok());
+        assert_eq!(inventory.entries[1].difficulty, SpinDifficulty::XD);
+        assert!(inventory.entries[1].summary.is_err());
+    }
+}
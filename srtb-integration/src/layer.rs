@@ -0,0 +1,77 @@
+use serde::Serialize;
+
+use crate::{
+    schema::write_payload, source_key, IntegrationError, PayloadVersion, RawSrtbFile,
+    SpinDifficulty,
+};
+
+/// Replaces the triggers of `base` that overlap the time range spanned by `layer` with the
+/// triggers of `layer`. `span` gives the start and end time of a trigger.
+pub(crate) fn overlay_triggers<T: Clone>(
+    base: &mut Vec<T>,
+    layer: &[T],
+    span: impl Fn(&T) -> (f32, f32),
+) {
+    let Some((start, end)) = layer
+        .iter()
+        .map(&span)
+        .reduce(|(start, end), (t1, t2)| (start.min(t1), end.max(t2)))
+    else {
+        return;
+    };
+    base.retain(|t| {
+        let (t1, t2) = span(t);
+        t2 < start || t1 > end
+    });
+    base.extend_from_slice(layer);
+    base.sort_by(|t1, t2| span(t1).0.total_cmp(&span(t2).0));
+}
+
+/// Writes `base` merged with the matching `overrides` to the key of every difficulty of the
+/// chart. Overrides for [`SpinDifficulty::AllDifficulties`] apply to every difficulty, and
+/// overrides are applied in order. The shared key is removed since it would be ignored anyway.
+/// The data is written in `version`.
+pub(crate) fn integrate_layers<D: Clone + Serialize>(
+    chart: &mut RawSrtbFile,
+    make_key: impl Fn(SpinDifficulty) -> String,
+    base: D,
+    overrides: &[(SpinDifficulty, D)],
+    overlay: impl Fn(&mut D, &D),
+    version: PayloadVersion,
+) -> Result<(), IntegrationError> {
+    for (diff, _) in overrides {
+        chart.ensure_difficulty(*diff)?;
+    }
+    for diff in chart.difficulties()? {
+        let mut data = base.clone();
+        for (_, layer) in overrides
+            .iter()
+            .filter(|(d, _)| *d == diff || *d == SpinDifficulty::AllDifficulties)
+        {
+            overlay(&mut data, layer);
+        }
+        let key = make_key(diff);
+        let value = write_payload(&data, version)?;
+        chart.set_large_string_value(&key, &value);
+        chart.remove_large_string_value(&source_key(&key));
+    }
+    let shared_key = make_key(SpinDifficulty::AllDifficulties);
+    chart.remove_large_string_value(&shared_key);
+    chart.remove_large_string_value(&source_key(&shared_key));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::layer::overlay_triggers;
+
+    #[test]
+    fn overlay() {
+        let mut base = vec![(0., 1.), (2., 3.), (4., 5.), (6., 7.)];
+        overlay_triggers(&mut base, &[(2.5, 2.5), (4.5, 4.5)], |t| *t);
+        assert_eq!(base, vec![(0., 1.), (2.5, 2.5), (4.5, 4.5), (6., 7.)]);
+
+        overlay_triggers(&mut base, &[], |t| *t);
+        assert_eq!(base.len(), 4);
+    }
+}
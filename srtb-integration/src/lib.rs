@@ -5,12 +5,41 @@ use thiserror::Error;
 pub(crate) mod color;
 
 mod chroma;
+mod compare;
+mod easing;
+mod expr;
+mod fan_out;
+mod include;
+mod inventory;
+mod layer;
+mod payload;
+mod profile;
+mod repeats;
+mod retime;
+mod schema;
 mod speeds;
 mod srtb;
+mod timing;
+mod track;
+mod transplant;
+mod validate;
 
-pub use chroma::ChromaIntegrator;
+pub use chroma::{ChromaIntegrator, ChromaNoteType};
+pub use compare::{ChangeKind, DifficultyChanges, TriggerChange};
+pub use fan_out::{KeyUsage, UsedKey};
+pub use include::{FileLoader, IncludeContext, ScriptLoader};
+pub use inventory::{IntegrationSummary, Inventory, InventoryEntry};
+pub use profile::SpeedProfile;
+pub use retime::Retime;
+pub use schema::PayloadVersion;
 pub use speeds::SpeedsIntegrator;
-pub use srtb::RawSrtbFile;
+pub use srtb::{RawSrtbFile, SaveOptions, SrtbFormat};
+pub use timing::{TimingMap, TICKS_PER_BEAT};
+pub use track::{
+    BpmMarker, ClipInfo, Note, TimeSignatureMarker, TrackData, TrackInfo, TrackInfoDifficulty,
+};
+pub use transplant::{TransplantReport, TransplantSelection};
+pub use validate::ValidationFinding;
 
 #[derive(Debug, Default, Display, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum SpinDifficulty {
@@ -35,23 +64,176 @@ impl SpinDifficulty {
         Self::RemiXD,
         Self::AllDifficulties,
     ];
+
+    /// Maps the game's `DifficultyType` value to a difficulty.
+    pub fn from_difficulty_type(difficulty_type: i32) -> Option<Self> {
+        let diff = match difficulty_type {
+            2 => Self::Easy,
+            3 => Self::Normal,
+            4 => Self::Hard,
+            5 => Self::Expert,
+            6 => Self::XD,
+            7 => Self::RemiXD,
+            _ => return None,
+        };
+        Some(diff)
+    }
+
+    pub fn difficulty_type(self) -> Option<i32> {
+        let difficulty_type = match self {
+            Self::Easy => 2,
+            Self::Normal => 3,
+            Self::Hard => 4,
+            Self::Expert => 5,
+            Self::XD => 6,
+            Self::RemiXD => 7,
+            Self::AllDifficulties => return None,
+        };
+        Some(difficulty_type)
+    }
+}
+
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntegratorKind {
+    #[default]
+    Speeds,
+    Chroma,
+}
+
+impl IntegratorKind {
+    pub const ALL: [Self; 2] = [Self::Speeds, Self::Chroma];
+
+    pub fn ext(self) -> &'static str {
+        match self {
+            IntegratorKind::Speeds => "speeds",
+            IntegratorKind::Chroma => "chroma",
+        }
+    }
+
+    /// Prefix shared by every key of the mod this integrator targets.
+    pub fn mod_prefix(self) -> &'static str {
+        match self {
+            IntegratorKind::Speeds => "SpeedHelper_",
+            IntegratorKind::Chroma => "SpeenChroma_",
+        }
+    }
+
+    pub fn integrator(self) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::Speeds => Box::new(SpeedsIntegrator),
+            IntegratorKind::Chroma => Box::new(ChromaIntegrator),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IntegrateOptions {
+    /// Also stores the script in the chart, so that extracting gives back the script as it was
+    /// written rather than a flattened list of triggers.
+    pub embed_source: bool,
+    /// Format version the data is written in.
+    pub payload_version: PayloadVersion,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractOptions {
+    /// Rebuilds `Repeat` blocks from triggers that repeat at a constant interval. Only supported
+    /// by the speeds integrator.
+    pub detect_repeats: bool,
+}
+
+/// Key under which the source script of the data stored in `key` is embedded.
+pub(crate) fn source_key(key: &str) -> String {
+    format!("{}_Source", key)
 }
 
 pub trait Integrator {
     fn file_extension(&self) -> String;
+    fn srtb_key(&self, diff: SpinDifficulty) -> String;
     fn integrate(
         &self,
         chart: &mut RawSrtbFile,
         data: &str,
         diff: SpinDifficulty,
+    ) -> Result<(), IntegrationError> {
+        self.integrate_with_options(chart, data, diff, IntegrateOptions::default())
+    }
+    fn integrate_with_options(
+        &self,
+        chart: &mut RawSrtbFile,
+        data: &str,
+        diff: SpinDifficulty,
+        options: IntegrateOptions,
+    ) -> Result<(), IntegrationError> {
+        self.integrate_with_includes(chart, data, diff, options, None)
+    }
+    /// Resolves the `Include "path"` directives of `data` through `includes`, which scripts need
+    /// to include others. Embedded sources have the included scripts inlined.
+    fn integrate_with_includes(
+        &self,
+        chart: &mut RawSrtbFile,
+        data: &str,
+        diff: SpinDifficulty,
+        options: IntegrateOptions,
+        includes: Option<&IncludeContext>,
     ) -> Result<(), IntegrationError>;
     fn extract(
         &self,
         chart: &RawSrtbFile,
         diff: SpinDifficulty,
+    ) -> Result<String, IntegrationError> {
+        self.extract_with_options(chart, diff, ExtractOptions::default())
+    }
+    /// Returns the embedded source script if it still compiles to the integrated data, or the
+    /// integrated triggers otherwise.
+    fn extract_with_options(
+        &self,
+        chart: &RawSrtbFile,
+        diff: SpinDifficulty,
+        options: ExtractOptions,
     ) -> Result<String, IntegrationError>;
+    /// Integrates `base` into the key of every difficulty of the chart, layering the matching
+    /// `overrides` scripts on top of it: per note type, the triggers of an override replace the
+    /// base triggers within the time range the override spans. An override for
+    /// [`SpinDifficulty::AllDifficulties`] applies to every difficulty, and overrides are applied
+    /// in the order given. The data is written in `version`.
+    fn integrate_layered(
+        &self,
+        chart: &mut RawSrtbFile,
+        base: &str,
+        overrides: &[(SpinDifficulty, &str)],
+        version: PayloadVersion,
+    ) -> Result<(), IntegrationError>;
     fn remove(&self, chart: &mut RawSrtbFile, diff: SpinDifficulty)
         -> Result<(), IntegrationError>;
+    fn summarize(
+        &self,
+        chart: &RawSrtbFile,
+        diff: SpinDifficulty,
+    ) -> Result<IntegrationSummary, IntegrationError>;
+    /// Lists the triggers stored for `diff` that differ between `old` and `new`. Missing data
+    /// counts as having no triggers.
+    fn changes(
+        &self,
+        old: &RawSrtbFile,
+        new: &RawSrtbFile,
+        diff: SpinDifficulty,
+    ) -> Result<Vec<TriggerChange>, IntegrationError>;
+    /// Changes the time of every trigger stored for `diff`. The embedded source script is removed
+    /// since it no longer matches. Does nothing if there is no data.
+    fn retime(
+        &self,
+        chart: &mut RawSrtbFile,
+        diff: SpinDifficulty,
+        retime: &Retime,
+    ) -> Result<(), IntegrationError>;
+    /// Rewrites the data stored for `diff` with sorted triggers and consistent formatting, so
+    /// that equivalent data is always stored the same way. Does nothing if there is no data.
+    fn canonicalize(
+        &self,
+        chart: &mut RawSrtbFile,
+        diff: SpinDifficulty,
+    ) -> Result<(), IntegrationError>;
 }
 
 #[derive(Error, Debug)]
@@ -65,11 +247,36 @@ pub enum IntegrationError {
     #[error("parsing error on line {0}: {1}")]
     ParsingError(usize, ParsingError),
 
+    #[error("parsing error in {file} on line {line}: {error}")]
+    IncludedParsingError {
+        file: String,
+        line: usize,
+        error: ParsingError,
+    },
+
     #[error("no integrated data found")]
     MissingData,
 
     #[error("operation cancelled")]
     Cancelled,
+
+    #[error("no unity object of type {0} found")]
+    MissingUnityObject(String),
+
+    #[error("json key \"{0}\" has no matching large string value")]
+    DanglingJsonKey(String),
+
+    #[error("chart has no {0} difficulty")]
+    MissingDifficulty(SpinDifficulty),
+
+    #[error("timing error: {0}")]
+    TimingError(ParsingError),
+
+    #[error("invalid scale factor {0}, it must be positive")]
+    InvalidScaleFactor(f32),
+
+    #[error("unsupported integrated data version: {0}")]
+    UnsupportedPayloadVersion(serde_json::Value),
 }
 
 #[derive(Error, Debug, PartialEq)]
@@ -107,7 +314,7 @@ pub enum ParsingError {
     #[error("unexpected EndRepeat instruction")]
     UnexpectedEndRepeat,
 
-    #[error("invalid command. Correct syntax is: Repeat X internal Y")]
+    #[error("invalid command. Correct syntax is: Repeat X interval Y [as I]")]
     InvalidRepeatCommand,
 
     #[error("no default color for note type {0}")]
@@ -124,4 +331,44 @@ pub enum ParsingError {
 
     #[error("amount of note types differ")]
     UnmatchedNoteTypesLength,
+
+    #[error("invalid time: {0}")]
+    InvalidTime(String),
+
+    #[error("invalid expression \"{expression}\" at column {column}: {message}")]
+    InvalidExpression {
+        expression: String,
+        column: usize,
+        message: String,
+    },
+
+    #[error("value \"{name}\" at column {column} does not exist")]
+    ValueNotFound { name: String, column: usize },
+
+    #[error("invalid value name: {0}")]
+    InvalidValueName(String),
+
+    #[error("invalid easing: {0}")]
+    InvalidEasing(String),
+
+    #[error("invalid wave: {0}")]
+    InvalidWave(String),
+
+    #[error("the chart has no BPM markers to convert between seconds and beats")]
+    MissingBpmMarkers,
+
+    #[error("invalid include, the path must be in double quotes: {0}")]
+    InvalidInclude(String),
+
+    #[error("scripts can only be included when integrating a script file")]
+    NoScriptLoader,
+
+    #[error("could not include {0}: {1}")]
+    IncludeFailed(String, String),
+
+    #[error("{0} ends up including itself")]
+    IncludeCycle(String),
+
+    #[error("invalid hsl color, expected hsl(hue in degrees, saturation, lightness): {0}")]
+    InvalidHslColor(String),
 }
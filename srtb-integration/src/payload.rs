@@ -0,0 +1,239 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    include::{IncludeContext, Script},
+    layer::integrate_layers,
+    schema::{read_payload, write_payload, Migration},
+    source_key, ExtractOptions, IntegrateOptions, IntegrationError, IntegrationSummary, Integrator,
+    PayloadVersion, RawSrtbFile, Retime, SpinDifficulty, TimingMap, TriggerChange,
+};
+
+/// The data an integrator stores in a chart. Integrators only differ by their data, so the
+/// [`Integrator`] implementation below is shared by all of them.
+pub(crate) trait Payload:
+    Clone + Default + PartialEq + Serialize + DeserializeOwned
+{
+    /// Key of the data shared by all difficulties, and prefix of the keys of each difficulty.
+    const SRTB_KEY: &'static str;
+    /// Upgrades from each older version, see [`read_payload`].
+    const MIGRATIONS: &'static [Migration];
+
+    /// Compiles the lines of a script whose `Include` directives are already expanded.
+    fn from_lines(lines: &[String], timing: &TimingMap) -> Result<Self, IntegrationError>;
+    fn to_text(&self, options: ExtractOptions) -> String;
+    /// Start and end time of every trigger.
+    fn spans(&self) -> Vec<(f32, f32)>;
+    fn changes(old: &Self, new: &Self) -> Vec<TriggerChange>;
+    /// Replaces the triggers within the time range `layer` spans with the triggers of `layer`.
+    fn overlay(&mut self, layer: &Self);
+    fn retime(&mut self, retime: &Retime) -> Result<(), IntegrationError>;
+    fn sort_triggers(&mut self);
+}
+
+/// Ties an integrator to the data it stores.
+pub(crate) trait PayloadIntegrator {
+    type Data: Payload;
+    const FILE_EXTENSION: &'static str;
+}
+
+pub(crate) fn make_key<P: Payload>(diff: SpinDifficulty) -> String {
+    if diff == SpinDifficulty::AllDifficulties {
+        P::SRTB_KEY.to_string()
+    } else {
+        format!("{}_{}", P::SRTB_KEY, diff.to_string().to_uppercase())
+    }
+}
+
+fn parse<P: Payload>(value: &str) -> Result<(P, PayloadVersion), IntegrationError> {
+    read_payload(value, P::MIGRATIONS)
+}
+
+/// The data stored for `diff`, if any.
+pub(crate) fn read<P: Payload>(
+    chart: &RawSrtbFile,
+    diff: SpinDifficulty,
+) -> Result<Option<P>, IntegrationError> {
+    chart
+        .get_large_string_value(&make_key::<P>(diff))
+        .map(|value| Ok(parse(value)?.0))
+        .transpose()
+}
+
+/// Compiles a script, which can't include others.
+pub(crate) fn compile<P: Payload>(text: &str, timing: &TimingMap) -> Result<P, IntegrationError> {
+    compile_script(&Script::expand(text, None)?, timing)
+}
+
+fn compile_script<P: Payload>(script: &Script, timing: &TimingMap) -> Result<P, IntegrationError> {
+    P::from_lines(script.lines(), timing).map_err(|e| script.locate(e))
+}
+
+/// Timing to compile scripts against. A chart whose clip can't be read still takes scripts timed
+/// in seconds: only beat and `measure:beat:tick` times fail, for lack of BPM markers.
+fn script_timing(chart: &RawSrtbFile) -> TimingMap {
+    chart.timing_map().unwrap_or_default()
+}
+
+/// Applies `edit` to the data stored for `diff` and writes it back in the same version. Does
+/// nothing if there is no data.
+fn rewrite<P: Payload>(
+    chart: &mut RawSrtbFile,
+    diff: SpinDifficulty,
+    edit: impl FnOnce(&mut P) -> Result<(), IntegrationError>,
+) -> Result<(), IntegrationError> {
+    let key = make_key::<P>(diff);
+    let Some(value) = chart.get_large_string_value(&key) else {
+        return Ok(());
+    };
+    let (mut data, version) = parse::<P>(value)?;
+    edit(&mut data)?;
+    let value = write_payload(&data, version)?;
+    chart.set_large_string_value(&key, &value);
+    Ok(())
+}
+
+impl<I: PayloadIntegrator> Integrator for I {
+    fn file_extension(&self) -> String {
+        I::FILE_EXTENSION.into()
+    }
+
+    fn srtb_key(&self, diff: SpinDifficulty) -> String {
+        make_key::<I::Data>(diff)
+    }
+
+    fn integrate_with_includes(
+        &self,
+        chart: &mut RawSrtbFile,
+        data: &str,
+        diff: SpinDifficulty,
+        options: IntegrateOptions,
+        includes: Option<&IncludeContext>,
+    ) -> Result<(), IntegrationError> {
+        chart.ensure_difficulty(diff)?;
+        let script = Script::expand(data, includes)?;
+        let full_data: I::Data = compile_script(&script, &script_timing(chart))?;
+        let key = self.srtb_key(diff);
+        let value = write_payload(&full_data, options.payload_version)?;
+        chart.set_large_string_value(&key, &value);
+        if options.embed_source {
+            chart.set_large_string_value(&source_key(&key), &script.embedded_source(data));
+        } else {
+            chart.remove_large_string_value(&source_key(&key));
+        }
+        Ok(())
+    }
+
+    fn integrate_layered(
+        &self,
+        chart: &mut RawSrtbFile,
+        base: &str,
+        overrides: &[(SpinDifficulty, &str)],
+        version: PayloadVersion,
+    ) -> Result<(), IntegrationError> {
+        let timing = script_timing(chart);
+        let base: I::Data = compile(base, &timing)?;
+        let overrides = overrides
+            .iter()
+            .map(|(diff, data)| Ok((*diff, compile(data, &timing)?)))
+            .collect::<Result<Vec<_>, IntegrationError>>()?;
+        integrate_layers(
+            chart,
+            make_key::<I::Data>,
+            base,
+            &overrides,
+            I::Data::overlay,
+            version,
+        )
+    }
+
+    fn extract_with_options(
+        &self,
+        chart: &RawSrtbFile,
+        diff: SpinDifficulty,
+        options: ExtractOptions,
+    ) -> Result<String, IntegrationError> {
+        let key = self.srtb_key(diff);
+        let value = chart
+            .get_large_string_value(&key)
+            .ok_or(IntegrationError::MissingData)?;
+        let (data, _) = parse::<I::Data>(value)?;
+        if let Some(source) = chart.get_large_string_value(&source_key(&key)) {
+            if compile::<I::Data>(source, &script_timing(chart))
+                .is_ok_and(|compiled| compiled == data)
+            {
+                return Ok(source.to_string());
+            }
+        }
+        Ok(data.to_text(options))
+    }
+
+    fn remove(
+        &self,
+        chart: &mut RawSrtbFile,
+        diff: SpinDifficulty,
+    ) -> Result<(), IntegrationError> {
+        let key = self.srtb_key(diff);
+        chart.remove_large_string_value(&key);
+        chart.remove_large_string_value(&source_key(&key));
+        Ok(())
+    }
+
+    fn summarize(
+        &self,
+        chart: &RawSrtbFile,
+        diff: SpinDifficulty,
+    ) -> Result<IntegrationSummary, IntegrationError> {
+        let value = chart
+            .get_large_string_value(&self.srtb_key(diff))
+            .ok_or(IntegrationError::MissingData)?;
+        let (data, _) = parse::<I::Data>(value)?;
+        let spans = data.spans();
+        let time_range = spans
+            .iter()
+            .copied()
+            .reduce(|(start, end), (t1, t2)| (start.min(t1), end.max(t2)));
+        Ok(IntegrationSummary {
+            trigger_count: spans.len(),
+            time_range,
+            payload_size: value.len(),
+        })
+    }
+
+    fn changes(
+        &self,
+        old: &RawSrtbFile,
+        new: &RawSrtbFile,
+        diff: SpinDifficulty,
+    ) -> Result<Vec<TriggerChange>, IntegrationError> {
+        let old = read::<I::Data>(old, diff)?.unwrap_or_default();
+        let new = read::<I::Data>(new, diff)?.unwrap_or_default();
+        Ok(I::Data::changes(&old, &new))
+    }
+
+    fn retime(
+        &self,
+        chart: &mut RawSrtbFile,
+        diff: SpinDifficulty,
+        retime: &Retime,
+    ) -> Result<(), IntegrationError> {
+        retime.check()?;
+        rewrite(chart, diff, |data: &mut I::Data| {
+            data.retime(retime)?;
+            data.sort_triggers();
+            Ok(())
+        })?;
+        chart.remove_large_string_value(&source_key(&self.srtb_key(diff)));
+        Ok(())
+    }
+
+    fn canonicalize(
+        &self,
+        chart: &mut RawSrtbFile,
+        diff: SpinDifficulty,
+    ) -> Result<(), IntegrationError> {
+        rewrite(chart, diff, |data: &mut I::Data| {
+            data.sort_triggers();
+            Ok(())
+        })
+    }
+}
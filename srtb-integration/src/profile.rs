@@ -0,0 +1,140 @@
+use crate::{
+    payload::read,
+    speeds::{text_to_speeds, SpeedTrigger, SpeedTriggersData},
+    IntegrationError, RawSrtbFile, SpinDifficulty, TimingMap,
+};
+
+/// Where the track is and how fast it scrolls at any time, following speed triggers the way the
+/// SpeedHelper mod applies them: the speed is 1 before the first trigger, holds until the next
+/// trigger, or changes linearly towards it if the trigger interpolates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeedProfile {
+    /// One section per trigger, sorted by time.
+    sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Section {
+    time: f32,
+    speed: f32,
+    /// Change of speed per second.
+    slope: f32,
+    /// Distance scrolled between the first trigger and the start of the section.
+    distance: f32,
+}
+
+impl Section {
+    fn speed_at(&self, time: f32) -> f32 {
+        self.speed + self.slope * (time - self.time)
+    }
+
+    fn distance_at(&self, time: f32) -> f32 {
+        self.distance + (self.speed + self.speed_at(time)) / 2. * (time - self.time)
+    }
+}
+
+impl SpeedProfile {
+    pub(crate) fn from_triggers(triggers: &[SpeedTrigger]) -> Self {
+        let mut triggers: Vec<_> = triggers.iter().collect();
+        triggers.sort_by(|t1, t2| t1.time.total_cmp(&t2.time));
+        let mut sections: Vec<Section> = vec![];
+        for (i, trigger) in triggers.iter().enumerate() {
+            let slope = match triggers.get(i + 1) {
+                Some(next) if trigger.interpolate && next.time > trigger.time => {
+                    (next.speed_multiplier - trigger.speed_multiplier) / (next.time - trigger.time)
+                }
+                _ => 0.,
+            };
+            let distance = sections
+                .last()
+                .map_or(0., |section| section.distance_at(trigger.time));
+            sections.push(Section {
+                time: trigger.time,
+                speed: trigger.speed_multiplier,
+                slope,
+                distance,
+            });
+        }
+        Self { sections }
+    }
+
+    /// Profile of the speeds integrated for `diff`.
+    pub fn from_chart(chart: &RawSrtbFile, diff: SpinDifficulty) -> Result<Self, IntegrationError> {
+        let data = read::<SpeedTriggersData>(chart, diff)?.ok_or(IntegrationError::MissingData)?;
+        Ok(Self::from_triggers(&data.triggers))
+    }
+
+    /// Profile of a speeds script, without integrating it.
+    pub fn from_script(script: &str, timing: &TimingMap) -> Result<Self, IntegrationError> {
+        Ok(Self::from_triggers(
+            &text_to_speeds(script, timing)?.triggers,
+        ))
+    }
+
+    /// The section `time` falls in, `None` before the first trigger.
+    fn section(&self, time: f32) -> Option<&Section> {
+        let index = self.sections.partition_point(|s| s.time <= time);
+        index.checked_sub(1).map(|index| &self.sections[index])
+    }
+
+    /// Speed multiplier at `time`.
+    pub fn multiplier_at(&self, time: f32) -> f32 {
+        self.section(time)
+            .map_or(1., |section| section.speed_at(time))
+    }
+
+    /// Distance scrolled since the first trigger, in seconds at normal speed.
+    fn distance_since_first(&self, time: f32) -> f32 {
+        match (self.section(time), self.sections.first()) {
+            (Some(section), _) => section.distance_at(time),
+            (None, Some(first)) => time - first.time,
+            (None, None) => time,
+        }
+    }
+
+    /// Distance the track has scrolled between the start of the chart and `time`, in seconds at
+    /// normal speed: a note at time `t` is `distance_at(t) - distance_at(now)` away.
+    pub fn distance_at(&self, time: f32) -> f32 {
+        self.distance_since_first(time) - self.distance_since_first(0.)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        track::test::CHART, Integrator, RawSrtbFile, SpeedProfile, SpeedsIntegrator,
+        SpinDifficulty, TimingMap,
+    };
+
+    #[test]
+    fn simulate() {
+        let profile =
+            SpeedProfile::from_script("1 2 true\n3 4\n5 0\n", &TimingMap::default()).unwrap();
+        assert_eq!(profile.multiplier_at(0.5), 1.);
+        assert_eq!(profile.multiplier_at(2.), 3.);
+        assert_eq!(profile.multiplier_at(4.), 4.);
+        assert_eq!(profile.multiplier_at(6.), 0.);
+
+        assert_eq!(profile.distance_at(1.), 1.);
+        assert_eq!(profile.distance_at(2.), 3.5);
+        assert_eq!(profile.distance_at(3.), 7.);
+        assert_eq!(profile.distance_at(5.), 15.);
+        assert_eq!(profile.distance_at(10.), 15.);
+        assert_eq!(profile.distance_at(-1.), -1.);
+
+        let profile = SpeedProfile::from_script("", &TimingMap::default()).unwrap();
+        assert_eq!(profile.distance_at(2.), 2.);
+    }
+
+    #[test]
+    fn from_chart() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        assert!(SpeedProfile::from_chart(&chart, SpinDifficulty::XD).is_err());
+        SpeedsIntegrator
+            .integrate(&mut chart, "-1 2\n0 0.5", SpinDifficulty::XD)
+            .unwrap();
+        let profile = SpeedProfile::from_chart(&chart, SpinDifficulty::XD).unwrap();
+        assert_eq!(profile.distance_at(2.), 1.);
+        assert_eq!(profile.distance_at(-1.), -2.);
+    }
+}
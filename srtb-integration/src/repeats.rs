@@ -0,0 +1,203 @@
+use std::fmt::Write;
+
+use crate::{
+    speeds::{speeds_to_text, text_to_speeds, SpeedTrigger, SpeedTriggersData},
+    TimingMap,
+};
+
+/// A line of a speeds script, or a `Repeat` block of them.
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    Trigger(SpeedTrigger),
+    Repeat {
+        count: i32,
+        interval: f32,
+        body: Vec<Block>,
+    },
+}
+
+/// The most triggers a repeated body can have. Longer periods are rare in hand-written scripts,
+/// and trying every length would make the search cubic in the number of triggers.
+const MAX_PERIOD: usize = 32;
+
+/// Turns triggers back into a script, rebuilding `Repeat` blocks (nested ones too) from runs of
+/// triggers that repeat at a constant interval. Falls back to one line per trigger if the script
+/// wouldn't compile back to exactly the same triggers.
+pub(crate) fn speeds_to_text_with_repeats(data: &SpeedTriggersData) -> String {
+    let mut text = String::new();
+    write_blocks(&mut text, &compress(&data.triggers), 0);
+    match text_to_speeds(&text, &TimingMap::default()) {
+        Ok(compiled) if compiled == *data => text,
+        _ => speeds_to_text(data),
+    }
+}
+
+/// Greedily replaces periodic runs of `triggers` with `Repeat` blocks, picking at each trigger
+/// the run that saves the most lines.
+fn compress(triggers: &[SpeedTrigger]) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut i = 0;
+    while i < triggers.len() {
+        match best_repeat(&triggers[i..]) {
+            Some((block, covered)) => {
+                blocks.push(block);
+                i += covered;
+            }
+            None => {
+                blocks.push(Block::Trigger(triggers[i].clone()));
+                i += 1;
+            }
+        }
+    }
+    blocks
+}
+
+/// Finds the repeating run at the start of `triggers` that saves the most lines, along with the
+/// number of triggers it covers. Bodies are at most [`MAX_PERIOD`] triggers long.
+fn best_repeat(triggers: &[SpeedTrigger]) -> Option<(Block, usize)> {
+    let mut best: Option<(usize, usize, i32, f32)> = None;
+    for len in 1..=(triggers.len() / 2).min(MAX_PERIOD) {
+        let interval = triggers[len].time - triggers[0].time;
+        if interval <= 0. {
+            continue;
+        }
+        let count = repetitions(triggers, len, interval);
+        // A block takes two more lines than its body.
+        let saved = (count * len).saturating_sub(len + 2);
+        if count >= 2 && saved > best.map_or(0, |(saved, ..)| saved) {
+            best = Some((saved, len, count as i32, interval));
+        }
+    }
+
+    let (_, len, count, interval) = best?;
+    let covered = &triggers[..len * count as usize];
+    // Prefer a compressed body, but only keep a block that compiles back exactly.
+    [compress(&triggers[..len]), blocks_of(&triggers[..len])]
+        .into_iter()
+        .map(|body| Block::Repeat {
+            count,
+            interval,
+            body,
+        })
+        .find(|block| {
+            let mut expanded = vec![];
+            expand(std::slice::from_ref(block), &mut vec![], &mut expanded);
+            expanded.sort_by(|t1, t2| t1.time.total_cmp(&t2.time));
+            expanded == covered
+        })
+        .map(|block| (block, covered.len()))
+}
+
+/// How many times the first `len` triggers repeat in a row, `interval` apart.
+fn repetitions(triggers: &[SpeedTrigger], len: usize, interval: f32) -> usize {
+    let pattern = &triggers[..len];
+    triggers
+        .chunks_exact(len)
+        .enumerate()
+        .take_while(|(k, chunk)| {
+            chunk.iter().zip(pattern).all(|(t, p)| {
+                t.speed_multiplier == p.speed_multiplier
+                    && t.interpolate == p.interpolate
+                    && t.time == p.time + interval * *k as f32
+            })
+        })
+        .count()
+}
+
+fn blocks_of(triggers: &[SpeedTrigger]) -> Vec<Block> {
+    triggers.iter().cloned().map(Block::Trigger).collect()
+}
+
+/// Computes the triggers `blocks` compile to, adding up repeat offsets the way the parser does.
+fn expand(blocks: &[Block], offsets: &mut Vec<(f32, i32)>, triggers: &mut Vec<SpeedTrigger>) {
+    for block in blocks {
+        match block {
+            Block::Trigger(trigger) => {
+                let mut time = trigger.time;
+                for (interval, iteration) in offsets.iter() {
+                    time += interval * *iteration as f32;
+                }
+                triggers.push(SpeedTrigger {
+                    time,
+                    ..trigger.clone()
+                });
+            }
+            Block::Repeat {
+                count,
+                interval,
+                body,
+            } => {
+                for iteration in 0..*count {
+                    offsets.push((*interval, iteration));
+                    expand(body, offsets, triggers);
+                    offsets.pop();
+                }
+            }
+        }
+    }
+}
+
+fn write_blocks(output: &mut String, blocks: &[Block], depth: usize) {
+    let indent = "    ".repeat(depth);
+    for block in blocks {
+        match block {
+            Block::Trigger(t) => {
+                let _ = writeln!(
+                    output,
+                    "{}{} {} {}",
+                    indent, t.time, t.speed_multiplier, t.interpolate
+                );
+            }
+            Block::Repeat {
+                count,
+                interval,
+                body,
+            } => {
+                let _ = writeln!(output, "{}Repeat {} interval {}", indent, count, interval);
+                write_blocks(output, body, depth + 1);
+                let _ = writeln!(output, "{}EndRepeat", indent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{repeats::speeds_to_text_with_repeats, speeds::text_to_speeds, TimingMap};
+
+    #[test]
+    fn recover_repeats() {
+        let script = "0 1 false\nRepeat 3 interval 2\n    Repeat 4 interval 0.25\n        1 2 true\n    EndRepeat\n    1.875 1 false\nEndRepeat\n8 0.5 false\n";
+        let data = text_to_speeds(script, &TimingMap::default()).unwrap();
+        assert_eq!(data.triggers.len(), 17);
+        assert_eq!(speeds_to_text_with_repeats(&data), script);
+    }
+
+    #[test]
+    fn nothing_to_recover() {
+        let script = "0 1 false\n1 2 false\n2 1 true\n";
+        let data = text_to_speeds(script, &TimingMap::default()).unwrap();
+        assert_eq!(speeds_to_text_with_repeats(&data), script);
+
+        let data = text_to_speeds(
+            "Repeat 50 interval 0.1\n0.3 1\nEndRepeat",
+            &TimingMap::default(),
+        )
+        .unwrap();
+        let text = speeds_to_text_with_repeats(&data);
+        assert_eq!(text_to_speeds(&text, &TimingMap::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn large_input() {
+        let mut script = String::new();
+        for i in 0..4000 {
+            let multiplier = if i % 1000 < 500 { i % 3 } else { i };
+            script += &format!("{} {} false\n", i as f32 * 0.25, multiplier);
+        }
+        let data = text_to_speeds(&script, &TimingMap::default()).unwrap();
+        let text = speeds_to_text_with_repeats(&data);
+        assert!(text.lines().count() < 2500);
+        assert_eq!(text_to_speeds(&text, &TimingMap::default()).unwrap(), data);
+    }
+}
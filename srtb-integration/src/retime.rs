@@ -0,0 +1,52 @@
+use crate::{IntegrationError, ParsingError, TimingMap};
+
+/// A change applied to the time of every integrated trigger, see [`crate::Integrator::retime`].
+/// Chroma triggers written with `Start` lines, stored as instant triggers at 0, set the colours a
+/// chart starts with and stay at 0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Retime {
+    /// Moves every trigger by this many seconds.
+    Shift(f32),
+    /// Stretches times away from `pivot` by `factor`, which must be positive.
+    Scale { pivot: f32, factor: f32 },
+    /// Keeps every trigger on the same beat when the BPM markers change from `old` to `new`.
+    Remap { old: TimingMap, new: TimingMap },
+    /// Keeps what happens from `start` to `end`: triggers starting before `start` or at or after
+    /// `end` are removed, changes crossing either edge are cut there, and the state reached at
+    /// `start` is set again there.
+    Clip { start: f32, end: f32 },
+}
+
+impl Retime {
+    /// Checks the retime can be applied, before any trigger is changed.
+    pub(crate) fn check(&self) -> Result<(), IntegrationError> {
+        match self {
+            Self::Scale { factor, .. } if !(factor.is_finite() && *factor > 0.) => {
+                Err(IntegrationError::InvalidScaleFactor(*factor))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn map_time(&self, time: f32) -> Result<f32, IntegrationError> {
+        match self {
+            Self::Shift(offset) => Ok(time + offset),
+            Self::Scale { pivot, factor } => Ok(pivot + (time - pivot) * factor),
+            Self::Remap { old, new } => old
+                .seconds_to_beats(time)
+                .and_then(|beats| new.beats_to_seconds(beats))
+                .ok_or(IntegrationError::TimingError(
+                    ParsingError::MissingBpmMarkers,
+                )),
+            Self::Clip { .. } => Ok(time),
+        }
+    }
+
+    /// The time range a [`Retime::Clip`] keeps.
+    pub(crate) fn window(&self) -> Option<(f32, f32)> {
+        match self {
+            Self::Clip { start, end } => Some((*start, *end)),
+            _ => None,
+        }
+    }
+}
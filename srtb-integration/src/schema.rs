@@ -0,0 +1,86 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use strum::Display;
+
+use crate::IntegrationError;
+
+const VERSION_FIELD: &str = "Version";
+
+/// Version of the format integrated data is stored in. Data without a version marker is
+/// [`PayloadVersion::V1`].
+#[derive(Debug, Default, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PayloadVersion {
+    /// The original format, without a version marker. Older tools may have left out fields
+    /// that have a sensible default.
+    #[default]
+    V1,
+    /// Same fields as V1, but every field is present and the data starts with a `Version`
+    /// marker, so that later format changes can be told apart from V1 data.
+    V2,
+}
+
+impl PayloadVersion {
+    pub const ALL: [Self; 2] = [Self::V1, Self::V2];
+    pub const LATEST: Self = Self::V2;
+
+    pub fn number(self) -> u64 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+        }
+    }
+
+    pub fn from_number(number: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.number() == number)
+    }
+}
+
+/// Upgrades a payload object to the next version.
+pub(crate) type Migration = fn(&mut Map<String, Value>);
+
+/// Reads integrated data written in any supported version. `migrations[i]` upgrades a payload
+/// of version `i + 1`, and every migration from the payload's version onwards is applied.
+pub(crate) fn read_payload<D: DeserializeOwned>(
+    value: &str,
+    migrations: &[Migration],
+) -> Result<(D, PayloadVersion), IntegrationError> {
+    let mut payload: Value =
+        serde_json::from_str(value).map_err(IntegrationError::SerdeJsonError)?;
+    let mut version = PayloadVersion::V1;
+    if let Value::Object(object) = &mut payload {
+        if let Some(marker) = object.remove(VERSION_FIELD) {
+            version = marker
+                .as_u64()
+                .and_then(PayloadVersion::from_number)
+                .ok_or(IntegrationError::UnsupportedPayloadVersion(marker))?;
+        }
+        for migrate in migrations.iter().skip(version.number() as usize - 1) {
+            migrate(object);
+        }
+    }
+    let data = serde_json::from_value(payload).map_err(IntegrationError::SerdeJsonError)?;
+    Ok((data, version))
+}
+
+pub(crate) fn write_payload<D: Serialize>(
+    data: &D,
+    version: PayloadVersion,
+) -> Result<String, IntegrationError> {
+    if version == PayloadVersion::V1 {
+        return serde_json::to_string(data).map_err(IntegrationError::SerdeJsonError);
+    }
+    let Value::Object(fields) =
+        serde_json::to_value(data).map_err(IntegrationError::SerdeJsonError)?
+    else {
+        unreachable!("payloads are structs");
+    };
+    let mut object = Map::new();
+    object.insert(VERSION_FIELD.into(), version.number().into());
+    object.extend(fields);
+    serde_json::to_string(&object).map_err(IntegrationError::SerdeJsonError)
+}
+
+/// Inserts `value` under `key` if the field is missing.
+pub(crate) fn default_field(object: &mut Map<String, Value>, key: &str, value: Value) {
+    object.entry(key).or_insert(value);
+}
@@ -1,46 +1,71 @@
 use serde::{Deserialize, Serialize};
-use std::fmt::Write;
+use serde_json::{Map, Value};
+use std::{f32::consts::TAU, fmt::Write, str::FromStr};
 
-use crate::{srtb::RawSrtbFile, IntegrationError, Integrator, ParsingError, SpinDifficulty};
+use crate::{
+    compare::compare_triggers,
+    easing::{Easing, DEFAULT_EASE_STEPS},
+    expr::{check_name, eval, eval_int, split_arguments, LoopVariable, Names},
+    layer::overlay_triggers,
+    payload::{compile, Payload, PayloadIntegrator},
+    repeats::speeds_to_text_with_repeats,
+    schema::{default_field, Migration},
+    timing::{resolve_time, ScriptTime, TimeContext, TimingMap},
+    ExtractOptions, IntegrationError, ParsingError, Retime, SpeedProfile, TriggerChange,
+};
 
-const SRTB_KEY: &str = "SpeedHelper_SpeedTriggers";
-
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "PascalCase")]
-struct SpeedTrigger {
-    time: f32,
-    speed_multiplier: f32,
+pub(crate) struct SpeedTrigger {
+    pub(crate) time: f32,
+    pub(crate) speed_multiplier: f32,
     #[serde(rename = "InterpolateToNextTrigger")]
-    interpolate: bool,
+    pub(crate) interpolate: bool,
+    /// Fields this tool doesn't know about, kept as they are.
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "PascalCase")]
-struct SpeedTriggersData {
-    triggers: Vec<SpeedTrigger>,
+pub(crate) struct SpeedTriggersData {
+    pub(crate) triggers: Vec<SpeedTrigger>,
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
+}
+
+pub(crate) fn text_to_speeds(
+    data: &str,
+    timing: &TimingMap,
+) -> Result<SpeedTriggersData, IntegrationError> {
+    compile(data, timing)
 }
 
-fn text_to_speeds(data: &str) -> Result<SpeedTriggersData, IntegrationError> {
+fn lines_to_speeds(
+    lines: &[String],
+    timing: &TimingMap,
+) -> Result<SpeedTriggersData, IntegrationError> {
     let mut triggers = vec![];
     let mut line_number = 0;
 
     let mut repeat_depth = 0;
     let mut repeat_counts = Vec::<i32>::new();
     let mut current_iterations = vec![];
-    let mut repeat_intervals = Vec::<f32>::new();
+    let mut repeat_intervals = Vec::<ScriptTime>::new();
     let mut goto_line_buf = vec![];
+    let mut loop_variables = Vec::<Option<LoopVariable>>::new();
+    let mut names = Names::new();
 
-    let lines: Vec<_> = data.lines().collect();
     while line_number < lines.len() {
         println!("Working on line {}", line_number);
-        let line = lines[line_number];
+        let line = &lines[line_number];
         let line = line.to_lowercase();
         let line = line.trim();
         if line.is_empty() || line.starts_with('#') {
             line_number += 1;
             continue;
         }
-        let line: Vec<_> = line.split_whitespace().collect();
+        let line = split_arguments(line);
 
         if line[0] == "repeat" {
             if line.len() < 4 {
@@ -58,24 +83,60 @@ fn text_to_speeds(data: &str) -> Result<SpeedTriggersData, IntegrationError> {
             }
 
             repeat_depth += 1;
-            repeat_counts.push(line[1].parse().map_err(|_| {
-                IntegrationError::ParsingError(
-                    line_number,
-                    ParsingError::InvalidInt(line[1].into()),
-                )
-            })?);
-            repeat_intervals.push(line[3].parse().map_err(|_| {
-                IntegrationError::ParsingError(
-                    line_number,
-                    ParsingError::InvalidFloat(line[3].into()),
-                )
-            })?);
+            repeat_counts.push(
+                eval_int(line[1], &names)
+                    .map_err(|e| IntegrationError::ParsingError(line_number, e))?,
+            );
+            repeat_intervals.push(
+                ScriptTime::parse_interval(line[3], &names)
+                    .map_err(|e| IntegrationError::ParsingError(line_number, e))?,
+            );
+            loop_variables.push(
+                LoopVariable::parse(&line[4..], &mut names)
+                    .map_err(|e| IntegrationError::ParsingError(line_number, e))?,
+            );
             current_iterations.push(0);
             goto_line_buf.push(line_number);
             line_number += 1;
             continue;
         }
 
+        if line[0] == "set" || line[0] == "marker" {
+            if line.len() < 3 {
+                return Err(IntegrationError::ParsingError(
+                    line_number,
+                    ParsingError::MissingArguments,
+                ));
+            }
+            let value = if line[0] == "set" {
+                eval(line[2], &names)
+            } else {
+                ScriptTime::parse(line[2], timing, &names).and_then(|time| {
+                    resolve_time(timing, time, &repeat_intervals, &current_iterations)
+                })
+            };
+            let value = check_name(line[1])
+                .and(value)
+                .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
+            names.insert(line[1].to_string(), value);
+            line_number += 1;
+            continue;
+        }
+
+        if matches!(line[0], "ease" | "wobble" | "pulse" | "freeze" | "hold") {
+            let context = TimeContext {
+                timing,
+                names: &names,
+                repeat_intervals: &repeat_intervals,
+                current_iterations: &current_iterations,
+            };
+            let generated = generate_speeds(&line, &context, &triggers)
+                .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
+            triggers.extend(generated);
+            line_number += 1;
+            continue;
+        }
+
         if line[0] == "endrepeat" {
             if repeat_depth == 0 {
                 return Err(IntegrationError::ParsingError(
@@ -86,6 +147,9 @@ fn text_to_speeds(data: &str) -> Result<SpeedTriggersData, IntegrationError> {
 
             current_iterations[repeat_depth - 1] += 1;
             if current_iterations[repeat_depth - 1] < repeat_counts[repeat_depth - 1] {
+                if let Some(variable) = &loop_variables[repeat_depth - 1] {
+                    variable.set(current_iterations[repeat_depth - 1], &mut names);
+                }
                 line_number = goto_line_buf[repeat_depth - 1] + 1;
                 continue;
             }
@@ -94,6 +158,9 @@ fn text_to_speeds(data: &str) -> Result<SpeedTriggersData, IntegrationError> {
             repeat_counts.pop();
             repeat_intervals.pop();
             goto_line_buf.pop();
+            if let Some(variable) = loop_variables.pop().flatten() {
+                variable.end(&mut names);
+            }
             line_number += 1;
             current_iterations.pop();
             continue;
@@ -106,12 +173,11 @@ fn text_to_speeds(data: &str) -> Result<SpeedTriggersData, IntegrationError> {
             ));
         }
 
-        let time = line[0].parse().map_err(|_| {
-            IntegrationError::ParsingError(line_number, ParsingError::InvalidFloat(line[0].into()))
-        })?;
-        let speed_multiplier = line[1].parse().map_err(|_| {
-            IntegrationError::ParsingError(line_number, ParsingError::InvalidFloat(line[1].into()))
-        })?;
+        let time = ScriptTime::parse(line[0], timing, &names)
+            .and_then(|time| resolve_time(timing, time, &repeat_intervals, &current_iterations))
+            .map_err(|e| IntegrationError::ParsingError(line_number, e))?;
+        let speed_multiplier =
+            eval(line[1], &names).map_err(|e| IntegrationError::ParsingError(line_number, e))?;
         let interpolate = if line.len() >= 3 {
             line[2].parse().map_err(|_| {
                 IntegrationError::ParsingError(
@@ -123,93 +189,415 @@ fn text_to_speeds(data: &str) -> Result<SpeedTriggersData, IntegrationError> {
             false
         };
         triggers.push(SpeedTrigger {
-            time: if repeat_depth > 0 {
-                let mut time = time;
-                for i in 0..repeat_depth {
-                    time += repeat_intervals[i] * current_iterations[i] as f32;
-                }
-                time
-            } else {
-                time
-            },
+            time,
             speed_multiplier,
             interpolate,
+            extra: Map::new(),
         });
         line_number += 1;
     }
 
     triggers.sort_by(|t1, t2| t1.time.total_cmp(&t2.time));
-    Ok(SpeedTriggersData { triggers })
+    Ok(SpeedTriggersData {
+        triggers,
+        extra: Map::new(),
+    })
+}
+
+/// Compiles a generator command into triggers:
+/// - `ease start end from to easing [steps]`
+/// - `wobble wave start end center amplitude period [phase] [steps]`, with `sine`, `triangle` or
+///   `square` waves, `phase` in periods and `steps` samples per period
+/// - `pulse time duration value [base]`
+/// - `freeze time duration`, or `hold`
+///
+/// Pulses and freezes go back to `base`, by default to the speeds set by `previous`, the triggers
+/// of the lines above: a speed change they cross goes on afterwards as if they weren't there.
+/// Triggers written further down don't count, even earlier ones.
+fn generate_speeds(
+    line: &[&str],
+    context: &TimeContext,
+    previous: &[SpeedTrigger],
+) -> Result<Vec<SpeedTrigger>, ParsingError> {
+    let required = match line[0] {
+        "ease" | "wobble" => 6,
+        "pulse" => 4,
+        _ => 3,
+    };
+    if line.len() < required {
+        return Err(ParsingError::MissingArguments);
+    }
+    let names = context.names;
+    let optional_int = |index: usize, default| {
+        let value = match line.get(index) {
+            Some(value) => eval_int(value, names)?,
+            None => default,
+        };
+        if value < 1 {
+            return Err(ParsingError::InvalidInt(line[index].into()));
+        }
+        Ok(value)
+    };
+
+    match line[0] {
+        "ease" => {
+            let start = context.time(line[1])?;
+            let end = context.time(line[2])?;
+            let from = eval(line[3], names)?;
+            let to = eval(line[4], names)?;
+            let easing: Easing = line[5].parse()?;
+            let steps = optional_int(6, DEFAULT_EASE_STEPS)?;
+            Ok(ease_speeds(start, end, from, to, easing, steps).collect())
+        }
+        "wobble" => {
+            if line.len() < 7 {
+                return Err(ParsingError::MissingArguments);
+            }
+            let wave: Wave = line[1].parse()?;
+            let start = context.time(line[2])?;
+            let end = context.time(line[3])?;
+            let center = eval(line[4], names)?;
+            let amplitude = eval(line[5], names)?;
+            if end <= start {
+                return Err(ParsingError::InvalidTime(line[3].into()));
+            }
+            let period = ScriptTime::parse_interval(line[6], names)?;
+            if !matches!(period, ScriptTime::Seconds(p) | ScriptTime::Beats(p) if p > 0. && p.is_finite())
+            {
+                return Err(ParsingError::InvalidTime(line[6].into()));
+            }
+            let phase = match line.get(7) {
+                Some(phase) => eval(phase, names)?,
+                None => 0.,
+            };
+            let steps = optional_int(8, DEFAULT_WOBBLE_STEPS)?;
+            let wobble = Wobble {
+                wave,
+                center,
+                amplitude,
+                phase,
+                steps,
+            };
+            wobble.triggers(start, end, period, context.timing)
+        }
+        _ => {
+            let (start, end) = context.span(line[1], line[2])?;
+            let value = match line[0] {
+                "pulse" => eval(line[3], names)?,
+                _ => 0.,
+            };
+            let profile = SpeedProfile::from_triggers(previous);
+            let mut triggers = vec![];
+            // Ends a speed change crossing the start where it would be, so that it doesn't head
+            // towards the pulse instead.
+            let before = previous
+                .iter()
+                .filter(|t| t.time < start)
+                .max_by(|t1, t2| t1.time.total_cmp(&t2.time));
+            if before.is_some_and(|t| t.interpolate) && previous.iter().any(|t| t.time > start) {
+                triggers.push(SpeedTrigger {
+                    time: start,
+                    speed_multiplier: profile.multiplier_at(start),
+                    interpolate: false,
+                    extra: Map::new(),
+                });
+            }
+            triggers.push(SpeedTrigger {
+                time: start,
+                speed_multiplier: value,
+                interpolate: false,
+                extra: Map::new(),
+            });
+            let after = match line.get(4).filter(|_| line[0] == "pulse") {
+                Some(base) => SpeedTrigger {
+                    time: end,
+                    speed_multiplier: eval(base, names)?,
+                    interpolate: false,
+                    extra: Map::new(),
+                },
+                None => {
+                    let resumed = previous
+                        .iter()
+                        .filter(|t| t.time <= end)
+                        .max_by(|t1, t2| t1.time.total_cmp(&t2.time));
+                    SpeedTrigger {
+                        time: end,
+                        speed_multiplier: profile.multiplier_at(end),
+                        interpolate: resumed.is_some_and(|t| t.interpolate)
+                            && previous.iter().any(|t| t.time > end),
+                        extra: Map::new(),
+                    }
+                }
+            };
+            triggers.push(after);
+            Ok(triggers)
+        }
+    }
+}
+
+/// Number of samples per period of a sine or triangle wobble when the script doesn't say.
+const DEFAULT_WOBBLE_STEPS: i32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wave {
+    Sine,
+    Triangle,
+    Square,
+}
+
+impl FromStr for Wave {
+    type Err = ParsingError;
+
+    fn from_str(wave: &str) -> Result<Self, Self::Err> {
+        match wave.to_lowercase().as_str() {
+            "sine" => Ok(Self::Sine),
+            "triangle" => Ok(Self::Triangle),
+            "square" => Ok(Self::Square),
+            _ => Err(ParsingError::InvalidWave(wave.into())),
+        }
+    }
 }
 
-fn speeds_to_text(data: &SpeedTriggersData) -> String {
+impl Wave {
+    /// Value of the wave between -1 and 1, `x` being in periods. Every wave starts at 0 (or 1 for
+    /// the square wave) and goes up first.
+    fn value(self, x: f32) -> f32 {
+        match self {
+            Self::Sine => (x * TAU).sin(),
+            Self::Triangle => 4. * ((x - 0.25).rem_euclid(1.) - 0.5).abs() - 1.,
+            Self::Square if x.rem_euclid(1.) < 0.5 => 1.,
+            Self::Square => -1.,
+        }
+    }
+}
+
+struct Wobble {
+    wave: Wave,
+    center: f32,
+    amplitude: f32,
+    phase: f32,
+    steps: i32,
+}
+
+impl Wobble {
+    /// Samples the wobble between `start` and `end`, going back to `center` at the end. A period
+    /// given in beats follows tempo changes. `end` must come after `start` and `period` must be
+    /// positive.
+    fn triggers(
+        &self,
+        start: f32,
+        end: f32,
+        period: ScriptTime,
+        timing: &TimingMap,
+    ) -> Result<Vec<SpeedTrigger>, ParsingError> {
+        // Maps a position in periods since the start to a time.
+        let (periods, to_time): (f32, Box<dyn Fn(f32) -> Option<f32>>) = match period {
+            ScriptTime::Seconds(period) => (
+                (end - start) / period,
+                Box::new(move |x| Some(start + x * period)),
+            ),
+            ScriptTime::Beats(period) => {
+                let start = timing
+                    .seconds_to_beats(start)
+                    .ok_or(ParsingError::MissingBpmMarkers)?;
+                let end = timing
+                    .seconds_to_beats(end)
+                    .ok_or(ParsingError::MissingBpmMarkers)?;
+                (
+                    (end - start) / period,
+                    Box::new(move |x| timing.beats_to_seconds(start + x * period)),
+                )
+            }
+        };
+        // Positions at which the speed changes: samples, or the edges of a square wave.
+        let positions: Vec<f32> = match self.wave {
+            Wave::Square => {
+                let first_edge = (self.phase * 2.).floor() + 1.;
+                std::iter::once(0.)
+                    .chain(
+                        (0..)
+                            .map(|i| (first_edge + i as f32) / 2. - self.phase)
+                            .take_while(|x| *x < periods),
+                    )
+                    .collect()
+            }
+            _ => {
+                let samples = (periods * self.steps as f32).ceil() as i32;
+                (0..samples).map(|i| i as f32 / self.steps as f32).collect()
+            }
+        };
+        let interpolate = self.wave != Wave::Square;
+        let mut triggers = positions
+            .into_iter()
+            .map(|x| {
+                Some(SpeedTrigger {
+                    time: to_time(x)?,
+                    speed_multiplier: self.center
+                        + self.amplitude * self.wave.value(x + self.phase),
+                    interpolate,
+                    extra: Map::new(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ParsingError::MissingBpmMarkers)?;
+        triggers.push(SpeedTrigger {
+            time: end,
+            speed_multiplier: self.center,
+            interpolate: false,
+            extra: Map::new(),
+        });
+        Ok(triggers)
+    }
+}
+
+/// Samples a transition from `from` to `to` between `start` and `end` along `easing`, linearly
+/// interpolating between `steps` segments and holding `to` at the end.
+fn ease_speeds(
+    start: f32,
+    end: f32,
+    from: f32,
+    to: f32,
+    easing: Easing,
+    steps: i32,
+) -> impl Iterator<Item = SpeedTrigger> {
+    (0..=steps).map(move |step| {
+        let progress = step as f32 / steps as f32;
+        SpeedTrigger {
+            time: start + (end - start) * progress,
+            speed_multiplier: from + (to - from) * easing.apply(progress),
+            interpolate: step < steps,
+            extra: Map::new(),
+        }
+    })
+}
+
+/// Keeps the speeds from `start` to `end`: triggers outside are removed, and triggers are added
+/// at `start` and `end` where the speed changes across them, so that the speed in between stays
+/// the same.
+fn clip_speeds(triggers: &mut Vec<SpeedTrigger>, start: f32, end: f32) {
+    triggers.sort_by(|t1, t2| t1.time.total_cmp(&t2.time));
+    let profile = SpeedProfile::from_triggers(triggers);
+    let mut clipped = vec![];
+    if let Some(previous) = triggers.iter().rev().find(|t| t.time < start) {
+        if !triggers.iter().any(|t| t.time == start) {
+            clipped.push(SpeedTrigger {
+                time: start,
+                speed_multiplier: profile.multiplier_at(start),
+                ..previous.clone()
+            });
+        }
+    }
+    clipped.extend(
+        triggers
+            .iter()
+            .filter(|t| start <= t.time && t.time < end)
+            .cloned(),
+    );
+    let cut_at_end = triggers.iter().any(|t| t.time >= end);
+    if cut_at_end && clipped.last().is_some_and(|t| t.interpolate) {
+        clipped.push(SpeedTrigger {
+            time: end,
+            speed_multiplier: profile.multiplier_at(end),
+            interpolate: false,
+            extra: Map::new(),
+        });
+    }
+    *triggers = clipped;
+}
+
+fn trigger_to_line(t: &SpeedTrigger) -> String {
+    format!("{} {} {}", t.time, t.speed_multiplier, t.interpolate)
+}
+
+pub(crate) fn speeds_to_text(data: &SpeedTriggersData) -> String {
     data.triggers.iter().fold(String::new(), |mut output, t| {
-        let _ = writeln!(
-            output,
-            "{} {} {}",
-            t.time, t.speed_multiplier, t.interpolate
-        );
+        let _ = writeln!(output, "{}", trigger_to_line(t));
         output
     })
 }
 
-fn make_key(diff: SpinDifficulty) -> String {
-    if diff == SpinDifficulty::AllDifficulties {
-        SRTB_KEY.to_string()
-    } else {
-        format!("{}_{}", SRTB_KEY, diff.to_string().to_uppercase())
+/// Fills in the fields older tools left out.
+fn migrate_v1(payload: &mut Map<String, Value>) {
+    default_field(payload, "Triggers", Value::Array(vec![]));
+    if let Some(Value::Array(triggers)) = payload.get_mut("Triggers") {
+        for trigger in triggers.iter_mut().filter_map(Value::as_object_mut) {
+            default_field(trigger, "InterpolateToNextTrigger", Value::Bool(false));
+        }
     }
 }
 
-pub struct SpeedsIntegrator;
+impl Payload for SpeedTriggersData {
+    const SRTB_KEY: &'static str = "SpeedHelper_SpeedTriggers";
+    const MIGRATIONS: &'static [Migration] = &[migrate_v1];
 
-impl Integrator for SpeedsIntegrator {
-    fn file_extension(&self) -> String {
-        "speeds".into()
+    fn from_lines(lines: &[String], timing: &TimingMap) -> Result<Self, IntegrationError> {
+        lines_to_speeds(lines, timing)
     }
 
-    fn integrate(
-        &self,
-        chart: &mut RawSrtbFile,
-        data: &str,
-        diff: SpinDifficulty,
-    ) -> Result<(), IntegrationError> {
-        let full_data = text_to_speeds(data)?;
-        let key = make_key(diff);
-        let value = serde_json::to_string(&full_data).map_err(IntegrationError::SerdeJsonError)?;
-        chart.set_large_string_value(&key, &value);
-        Ok(())
+    fn to_text(&self, options: ExtractOptions) -> String {
+        if options.detect_repeats {
+            speeds_to_text_with_repeats(self)
+        } else {
+            speeds_to_text(self)
+        }
     }
 
-    fn extract(
-        &self,
-        chart: &RawSrtbFile,
-        diff: SpinDifficulty,
-    ) -> Result<String, IntegrationError> {
-        let key = make_key(diff);
-        let value = chart
-            .get_large_string_value(&key)
-            .ok_or(IntegrationError::MissingData)?;
-        let data: SpeedTriggersData =
-            serde_json::from_str(&value).map_err(IntegrationError::SerdeJsonError)?;
-        let str = speeds_to_text(&data);
-        Ok(str)
-    }
-
-    fn remove(
-        &self,
-        chart: &mut RawSrtbFile,
-        diff: SpinDifficulty,
-    ) -> Result<(), IntegrationError> {
-        let key = make_key(diff);
-        chart.remove_large_string_value(&key);
+    fn spans(&self) -> Vec<(f32, f32)> {
+        self.triggers.iter().map(|t| (t.time, t.time)).collect()
+    }
+
+    fn changes(old: &Self, new: &Self) -> Vec<TriggerChange> {
+        compare_triggers(
+            None,
+            &old.triggers,
+            &new.triggers,
+            |t| t.time,
+            trigger_to_line,
+        )
+    }
+
+    fn overlay(&mut self, layer: &Self) {
+        overlay_triggers(&mut self.triggers, &layer.triggers, |t| (t.time, t.time))
+    }
+
+    fn retime(&mut self, retime: &Retime) -> Result<(), IntegrationError> {
+        if let Some((start, end)) = retime.window() {
+            clip_speeds(&mut self.triggers, start, end);
+        }
+        for trigger in &mut self.triggers {
+            trigger.time = retime.map_time(trigger.time)?;
+        }
         Ok(())
     }
+
+    fn sort_triggers(&mut self) {
+        self.triggers.sort_by(|t1, t2| t1.time.total_cmp(&t2.time));
+    }
+}
+
+pub struct SpeedsIntegrator;
+
+impl PayloadIntegrator for SpeedsIntegrator {
+    type Data = SpeedTriggersData;
+    const FILE_EXTENSION: &'static str = "speeds";
 }
 
 #[cfg(test)]
 mod test {
-    use crate::speeds::{speeds_to_text, text_to_speeds, SpeedTrigger, SpeedTriggersData};
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    use serde_json::Map;
+
+    use crate::{
+        speeds::{speeds_to_text, text_to_speeds, SpeedTrigger, SpeedTriggersData},
+        track::test::CHART,
+        BpmMarker, ClipInfo, IncludeContext, IntegrateOptions, IntegrationError, Integrator,
+        ParsingError, PayloadVersion, RawSrtbFile, Retime, SpeedsIntegrator, SpinDifficulty,
+        TimingMap,
+    };
 
     #[test]
     fn to_speeds() {
@@ -224,20 +612,23 @@ mod test {
                 time: 0.,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 1.5,
                 speed_multiplier: 2.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 2.,
                 speed_multiplier: 1.5,
                 interpolate: true,
+                extra: Map::new(),
             },
         ];
 
-        let speeds = text_to_speeds(speeds).unwrap();
+        let speeds = text_to_speeds(speeds, &TimingMap::default()).unwrap();
         assert_eq!(speeds.triggers, expected_speeds);
     }
 
@@ -248,19 +639,25 @@ mod test {
                 time: 0.,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 1.5,
                 speed_multiplier: 2.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 2.,
                 speed_multiplier: 1.5,
                 interpolate: true,
+                extra: Map::new(),
             },
         ];
-        let speeds = SpeedTriggersData { triggers };
+        let speeds = SpeedTriggersData {
+            triggers,
+            extra: Map::new(),
+        };
 
         let expected_speeds = "0 1 false\n1.5 2 false\n2 1.5 true\n";
 
@@ -282,35 +679,41 @@ mod test {
                 time: 0.,
                 speed_multiplier: 0.,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 0.75,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 1.,
                 speed_multiplier: 0.,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 1.75,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 2.,
                 speed_multiplier: 0.,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 2.75,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
         ];
 
-        let speeds = text_to_speeds(speeds).unwrap();
+        let speeds = text_to_speeds(speeds, &TimingMap::default()).unwrap();
         assert_eq!(speeds.triggers, expected_speeds);
     }
 
@@ -330,65 +733,614 @@ mod test {
                 time: 1.,
                 speed_multiplier: 0.5,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 1.25,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 1.5,
                 speed_multiplier: 0.5,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 1.75,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 3.,
                 speed_multiplier: 0.5,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 3.25,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 3.5,
                 speed_multiplier: 0.5,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 3.75,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 5.,
                 speed_multiplier: 0.5,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 5.25,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 5.5,
                 speed_multiplier: 0.5,
                 interpolate: true,
+                extra: Map::new(),
             },
             SpeedTrigger {
                 time: 5.75,
                 speed_multiplier: 1.,
                 interpolate: false,
+                extra: Map::new(),
             },
         ];
 
-        let speeds = text_to_speeds(speeds).unwrap();
+        let speeds = text_to_speeds(speeds, &TimingMap::default()).unwrap();
         assert_eq!(speeds.triggers, expected_speeds);
     }
+
+    #[test]
+    fn integrate_missing_difficulty() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let err = SpeedsIntegrator
+            .integrate(&mut chart, "0 1", SpinDifficulty::Easy)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            IntegrationError::MissingDifficulty(SpinDifficulty::Easy)
+        ));
+
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 1", SpinDifficulty::Normal)
+            .unwrap();
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 1", SpinDifficulty::AllDifficulties)
+            .unwrap();
+    }
+
+    #[test]
+    fn embedded_source() {
+        let source = "# Intro\nRepeat 2 interval 1.0\n0 1\n0.5 2 true\nEndRepeat\n";
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let options = IntegrateOptions {
+            embed_source: true,
+            ..Default::default()
+        };
+        SpeedsIntegrator
+            .integrate_with_options(&mut chart, source, SpinDifficulty::Normal, options)
+            .unwrap();
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::Normal)
+                .unwrap(),
+            source
+        );
+
+        chart.set_large_string_value(
+            "SpeedHelper_SpeedTriggers_NORMAL",
+            r#"{"Triggers":[{"Time":0.0,"SpeedMultiplier":3.0,"InterpolateToNextTrigger":false}]}"#,
+        );
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::Normal)
+                .unwrap(),
+            "0 3 false\n"
+        );
+
+        SpeedsIntegrator
+            .integrate(&mut chart, source, SpinDifficulty::Normal)
+            .unwrap();
+        assert!(chart
+            .get_large_string_value("SpeedHelper_SpeedTriggers_NORMAL_Source")
+            .is_none());
+    }
+
+    #[test]
+    fn unreadable_timing() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let options = IntegrateOptions {
+            embed_source: true,
+            ..Default::default()
+        };
+        SpeedsIntegrator
+            .integrate_with_options(&mut chart, "0 1\n2b 2", SpinDifficulty::XD, options)
+            .unwrap();
+        chart.set_large_string_value("SO_ClipInfo_ClipInfo_0", "not json");
+        // The source can't be compiled anymore, the triggers are printed instead.
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            "0 1 false\n1 2 false\n"
+        );
+
+        // Only scripts using beats need the clip.
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 1\n1 2", SpinDifficulty::Normal)
+            .unwrap();
+        assert!(matches!(
+            SpeedsIntegrator.integrate(&mut chart, "0 1\n1:2 2", SpinDifficulty::Normal),
+            Err(IntegrationError::ParsingError(
+                1,
+                ParsingError::MissingBpmMarkers
+            ))
+        ));
+    }
+
+    #[test]
+    fn integrate_layered() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 5", SpinDifficulty::AllDifficulties)
+            .unwrap();
+        let base = "0 1\n4 2\n8 1\n12 3\n";
+        SpeedsIntegrator
+            .integrate_layered(
+                &mut chart,
+                base,
+                &[(SpinDifficulty::XD, "4 0.5\n6 1\n8 2")],
+                PayloadVersion::V2,
+            )
+            .unwrap();
+        assert!(chart
+            .get_large_string_value("SpeedHelper_SpeedTriggers_XD")
+            .unwrap()
+            .starts_with(r#"{"Version":2,"#));
+
+        assert!(chart
+            .get_large_string_value("SpeedHelper_SpeedTriggers")
+            .is_none());
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::Normal)
+                .unwrap(),
+            "0 1 false\n4 2 false\n8 1 false\n12 3 false\n"
+        );
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            "0 1 false\n4 0.5 false\n6 1 false\n8 2 false\n12 3 false\n"
+        );
+
+        SpeedsIntegrator
+            .integrate_layered(
+                &mut chart,
+                base,
+                &[
+                    (SpinDifficulty::AllDifficulties, "12 4"),
+                    (SpinDifficulty::XD, "12 5"),
+                ],
+                PayloadVersion::V2,
+            )
+            .unwrap();
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::Normal)
+                .unwrap(),
+            "0 1 false\n4 2 false\n8 1 false\n12 4 false\n"
+        );
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            "0 1 false\n4 2 false\n8 1 false\n12 5 false\n"
+        );
+
+        assert!(matches!(
+            SpeedsIntegrator.integrate_layered(
+                &mut chart,
+                base,
+                &[(SpinDifficulty::Easy, "")],
+                PayloadVersion::V1
+            ),
+            Err(IntegrationError::MissingDifficulty(SpinDifficulty::Easy))
+        ));
+    }
+
+    #[test]
+    fn payload_versions() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        chart.set_large_string_value(
+            "SpeedHelper_SpeedTriggers",
+            r#"{"Triggers":[{"Time":1.0,"SpeedMultiplier":2.0}]}"#,
+        );
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::AllDifficulties)
+                .unwrap(),
+            "1 2 false\n"
+        );
+
+        let options = IntegrateOptions {
+            payload_version: PayloadVersion::V2,
+            ..Default::default()
+        };
+        SpeedsIntegrator
+            .integrate_with_options(&mut chart, "1 2", SpinDifficulty::AllDifficulties, options)
+            .unwrap();
+        assert_eq!(
+            chart.get_large_string_value("SpeedHelper_SpeedTriggers"),
+            Some(
+                r#"{"Version":2,"Triggers":[{"Time":1.0,"SpeedMultiplier":2.0,"InterpolateToNextTrigger":false}]}"#
+            )
+        );
+        SpeedsIntegrator
+            .canonicalize(&mut chart, SpinDifficulty::AllDifficulties)
+            .unwrap();
+        assert!(chart
+            .get_large_string_value("SpeedHelper_SpeedTriggers")
+            .unwrap()
+            .starts_with(r#"{"Version":2,"#));
+
+        chart.set_large_string_value(
+            "SpeedHelper_SpeedTriggers",
+            r#"{"Version":9,"Triggers":[]}"#,
+        );
+        assert!(matches!(
+            SpeedsIntegrator.extract(&chart, SpinDifficulty::AllDifficulties),
+            Err(IntegrationError::UnsupportedPayloadVersion(_))
+        ));
+    }
+
+    #[test]
+    fn beat_times() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let speeds = "4b 2\nRepeat 2 interval 4b\n3:1 1\nEndRepeat\n";
+        SpeedsIntegrator
+            .integrate(&mut chart, speeds, SpinDifficulty::Normal)
+            .unwrap();
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::Normal)
+                .unwrap(),
+            "2 2 false\n4 1 false\n6 1 false\n"
+        );
+
+        assert!(matches!(
+            text_to_speeds("4b 2", &TimingMap::default()),
+            Err(IntegrationError::ParsingError(
+                0,
+                ParsingError::MissingBpmMarkers
+            ))
+        ));
+    }
+
+    #[test]
+    fn expressions() {
+        let speeds = "Repeat 2*2 interval 1/2\n(1 + 0.5) max(1, 2)*1.5\nEndRepeat\n";
+        let speeds = text_to_speeds(speeds, &TimingMap::default()).unwrap();
+        assert_eq!(speeds.triggers.len(), 4);
+        assert_eq!(speeds.triggers[3].time, 3.);
+        assert_eq!(speeds.triggers[3].speed_multiplier, 3.);
+
+        assert!(matches!(
+            text_to_speeds("0 1\n1 2+*3", &TimingMap::default()),
+            Err(IntegrationError::ParsingError(
+                1,
+                ParsingError::InvalidExpression { column: 3, .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn named_values() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let speeds = "Set fast 2.5\nMarker drop 5:1\n0 1\ndrop fast\ndrop+1 fast/2 true\n";
+        SpeedsIntegrator
+            .integrate(&mut chart, speeds, SpinDifficulty::Normal)
+            .unwrap();
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::Normal)
+                .unwrap(),
+            "0 1 false\n8 2.5 false\n9 1.25 true\n"
+        );
+        assert_eq!(
+            SpeedsIntegrator
+                .summarize(&chart, SpinDifficulty::Normal)
+                .unwrap()
+                .time_range,
+            Some((0., 9.))
+        );
+
+        assert!(matches!(
+            text_to_speeds("drop 1", &TimingMap::default()),
+            Err(IntegrationError::ParsingError(0, ParsingError::ValueNotFound { name, .. })) if name == "drop"
+        ));
+        assert!(matches!(
+            text_to_speeds("Set pi 3", &TimingMap::default()),
+            Err(IntegrationError::ParsingError(
+                0,
+                ParsingError::InvalidValueName(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn ease() {
+        let speeds = "0 1\nease 1 2 1 3 inOutCubic 4\nease 4 5 3 1 linear\n";
+        let speeds = text_to_speeds(speeds, &TimingMap::default()).unwrap();
+        assert_eq!(
+            speeds_to_text(&SpeedTriggersData {
+                triggers: speeds.triggers[..6].to_vec(),
+                extra: Map::new(),
+            }),
+            "0 1 false\n1 1 true\n1.25 1.125 true\n1.5 2 true\n1.75 2.875 true\n2 3 false\n"
+        );
+        assert_eq!(speeds.triggers.len(), 6 + 17);
+        assert_eq!(speeds.triggers[14].time, 4.5);
+        assert_eq!(speeds.triggers[14].speed_multiplier, 2.);
+
+        assert!(matches!(
+            text_to_speeds("ease 0 1 1 2 inWobble", &TimingMap::default()),
+            Err(IntegrationError::ParsingError(
+                0,
+                ParsingError::InvalidEasing(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn generators() {
+        let text = |speeds| speeds_to_text(&text_to_speeds(speeds, &TimingMap::default()).unwrap());
+        assert_eq!(
+            text("wobble triangle 0 1 2 1 1 0 4"),
+            "0 2 true\n0.25 3 true\n0.5 2 true\n0.75 1 true\n1 2 false\n"
+        );
+        assert_eq!(
+            text("wobble square 0 1.5 1 0.5 1 0.25"),
+            "0 1.5 false\n0.25 0.5 false\n0.75 1.5 false\n1.25 0.5 false\n1.5 1 false\n"
+        );
+        assert_eq!(
+            text("0 1 true\n2 3\npulse 1 0.5 4\nfreeze 3 1"),
+            "0 1 true\n1 2 false\n1 4 false\n1.5 2.5 true\n2 3 false\n3 0 false\n4 3 false\n"
+        );
+        assert_eq!(
+            text("hold 1 0.5\npulse 2 1 3 0.5"),
+            "1 0 false\n1.5 1 false\n2 3 false\n3 0.5 false\n"
+        );
+
+        let chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let speeds =
+            text_to_speeds("wobble sine 7 9 1 1 2b 0 2", &chart.timing_map().unwrap()).unwrap();
+        let times: Vec<_> = speeds.triggers.iter().map(|t| t.time).collect();
+        // The period shortens from one second to half a second at the tempo change at 8s.
+        assert_eq!(times, vec![7., 7.5, 8., 8.25, 8.5, 8.75, 9.]);
+
+        for (wobble, argument) in [
+            ("wobble sine 0 1 1 1 0", "0"),
+            ("wobble sine 0 1 1 1 -1b", "-1b"),
+            ("wobble square 2 1 1 1 1", "1"),
+            ("wobble square 1 1 1 1 1", "1"),
+        ] {
+            assert!(matches!(
+                text_to_speeds(wobble, &TimingMap::default()),
+                Err(IntegrationError::ParsingError(0, ParsingError::InvalidTime(time))) if time == argument
+            ));
+        }
+        assert!(matches!(
+            text_to_speeds("wobble saw 0 1 1 1 1", &TimingMap::default()),
+            Err(IntegrationError::ParsingError(
+                0,
+                ParsingError::InvalidWave(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn retime() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let options = IntegrateOptions {
+            embed_source: true,
+            ..Default::default()
+        };
+        SpeedsIntegrator
+            .integrate_with_options(
+                &mut chart,
+                "0 1\n2 2 true\n4 1\n",
+                SpinDifficulty::XD,
+                options,
+            )
+            .unwrap();
+        let mut retime = |retime| {
+            SpeedsIntegrator
+                .retime(&mut chart, SpinDifficulty::XD, &retime)
+                .unwrap();
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap()
+        };
+        assert_eq!(
+            retime(Retime::Shift(1.)),
+            "1 1 false\n3 2 true\n5 1 false\n"
+        );
+        assert_eq!(
+            retime(Retime::Scale {
+                pivot: 1.,
+                factor: 0.5
+            }),
+            "1 1 false\n2 2 true\n3 1 false\n"
+        );
+        assert_eq!(
+            retime(Retime::Clip { start: 2., end: 3. }),
+            "2 2 true\n3 1 false\n"
+        );
+        // The speed change crossing both edges is cut there.
+        assert_eq!(
+            retime(Retime::Clip {
+                start: 2.5,
+                end: 2.75
+            }),
+            "2.5 1.5 true\n2.75 1.25 false\n"
+        );
+
+        // From 120 BPM throughout to the chart's tempo, which doubles at 8s.
+        SpeedsIntegrator
+            .integrate(&mut chart, "0 1\n4 2\n10 1\n", SpinDifficulty::XD)
+            .unwrap();
+        let old = TimingMap::new(&ClipInfo {
+            bpm_markers: vec![BpmMarker {
+                clip_time: 0.,
+                beat_length: 0.5,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let new = chart.timing_map().unwrap();
+        let remap = Retime::Remap { old, new };
+        SpeedsIntegrator
+            .retime(&mut chart, SpinDifficulty::XD, &remap)
+            .unwrap();
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            "0 1 false\n4 2 false\n9 1 false\n"
+        );
+
+        let remap = Retime::Remap {
+            old: TimingMap::default(),
+            new: TimingMap::default(),
+        };
+        assert!(matches!(
+            SpeedsIntegrator.retime(&mut chart, SpinDifficulty::XD, &remap),
+            Err(IntegrationError::TimingError(
+                ParsingError::MissingBpmMarkers
+            ))
+        ));
+        for factor in [0., -1., f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                SpeedsIntegrator.retime(
+                    &mut chart,
+                    SpinDifficulty::XD,
+                    &Retime::Scale { pivot: 0., factor }
+                ),
+                Err(IntegrationError::InvalidScaleFactor(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn includes() {
+        let files = HashMap::from([
+            (
+                PathBuf::from("charts/fx/bump.txt"),
+                "0 2\n0.5 1\n".to_string(),
+            ),
+            (
+                PathBuf::from("charts/fx/broken.txt"),
+                "0 1\n1 x\n".to_string(),
+            ),
+        ]);
+        let includes = IncludeContext {
+            path: Path::new("charts/song.speeds"),
+            loader: &files,
+        };
+        let options = IntegrateOptions {
+            embed_source: true,
+            ..Default::default()
+        };
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let speeds = "Repeat 2 interval 1\nInclude \"fx/bump.txt\"\nEndRepeat\n";
+        SpeedsIntegrator
+            .integrate_with_includes(
+                &mut chart,
+                speeds,
+                SpinDifficulty::XD,
+                options,
+                Some(&includes),
+            )
+            .unwrap();
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&chart, SpinDifficulty::XD)
+                .unwrap(),
+            "Repeat 2 interval 1\n0 2\n0.5 1\nEndRepeat\n"
+        );
+
+        let err = SpeedsIntegrator
+            .integrate_with_includes(
+                &mut chart,
+                "0 1\ninclude \"fx/broken.txt\"",
+                SpinDifficulty::XD,
+                options,
+                Some(&includes),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "parsing error in charts/fx/broken.txt on line 1: value \"x\" at column 1 does not exist"
+        );
+    }
+
+    #[test]
+    fn loop_variables() {
+        let speeds = "Set i 10\nRepeat 3 interval 1 as i\n    Repeat 2 interval 0.25 as j\n        0 1+i+j/2\n    EndRepeat\nEndRepeat\n4 i\n";
+        let speeds = text_to_speeds(speeds, &TimingMap::default()).unwrap();
+        let multipliers: Vec<_> = speeds.triggers.iter().map(|t| t.speed_multiplier).collect();
+        assert_eq!(multipliers, vec![1., 1.5, 2., 2.5, 3., 3.5, 10.]);
+
+        let speeds = text_to_speeds(
+            "Repeat 4 interval 0 as i\ni*i 1\nEndRepeat",
+            &TimingMap::default(),
+        )
+        .unwrap();
+        let times: Vec<_> = speeds.triggers.iter().map(|t| t.time).collect();
+        assert_eq!(times, vec![0., 1., 4., 9.]);
+
+        let speeds = text_to_speeds(
+            "Repeat 2 interval 1 foo\n0 1\nEndRepeat\nRepeat 2 interval 1 as i foo\ni 2\nEndRepeat",
+            &TimingMap::default(),
+        )
+        .unwrap();
+        let times: Vec<_> = speeds.triggers.iter().map(|t| t.time).collect();
+        assert_eq!(times, vec![0., 0., 1., 2.]);
+
+        assert!(matches!(
+            text_to_speeds("Repeat 2 interval 1 as\nEndRepeat", &TimingMap::default()),
+            Err(IntegrationError::ParsingError(
+                0,
+                ParsingError::InvalidRepeatCommand
+            ))
+        ));
+        assert!(matches!(
+            text_to_speeds(
+                "Repeat 2 interval 1 as i\nEndRepeat\n0 i",
+                &TimingMap::default()
+            ),
+            Err(IntegrationError::ParsingError(
+                2,
+                ParsingError::ValueNotFound { .. }
+            ))
+        ));
+    }
 }
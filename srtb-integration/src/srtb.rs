@@ -1,34 +1,321 @@
-use std::{fs, path::Path};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, DeserializeOwned, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{value::RawValue, Value};
 
-use crate::IntegrationError;
+use crate::{IntegrationError, IntegratorKind, SpinDifficulty};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Every field of a JSON object, in the order it was read, duplicate keys included.
+///
+/// Fields the program knows about are left as `null` placeholders so they can be written back at
+/// their original position, while unknown fields are kept as the exact text that was read, so
+/// that numbers such as `1E-05` or `1.50` are written back unchanged.
+#[derive(Debug, Clone, Default)]
+struct Fields(Vec<(String, Box<RawValue>)>);
+
+impl Fields {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&String, &RawValue)> {
+        self.0.iter().map(|(key, value)| (key, &**value))
+    }
+
+    fn contains_key(&self, name: &str) -> bool {
+        self.0.iter().any(|(key, _)| key == name)
+    }
+
+    /// The last value of the field `name`, like a JSON object with duplicate keys reads as.
+    fn get(&self, name: &str) -> Option<&RawValue> {
+        self.iter()
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+            .last()
+    }
+}
+
+impl<'de> Deserialize<'de> for Fields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = Fields;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Fields, A::Error> {
+                let mut fields = vec![];
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(Fields(fields))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+fn take_field<T: DeserializeOwned, E: de::Error>(
+    fields: &mut Fields,
+    name: &'static str,
+) -> Result<T, E> {
+    let value = match fields.get(name) {
+        Some(value) => serde_json::from_str(value.get()).map_err(E::custom)?,
+        None => return T::deserialize(Value::Null).map_err(|_| E::missing_field(name)),
+    };
+    for (_, field) in fields.0.iter_mut().filter(|(key, _)| key == name) {
+        *field = RawValue::NULL.to_owned();
+    }
+    Ok(value)
+}
+
+/// Serializes an object made of `fields` in order, with the known fields substituted in. Known
+/// fields that were not read from a file are appended at the end.
+macro_rules! serialize_fields {
+    ($serializer:expr, $fields:expr, { $($name:literal => $value:expr),+ $(,)? }) => {{
+        let mut map = $serializer.serialize_map(None)?;
+        for (key, value) in $fields.iter() {
+            match key.as_str() {
+                $($name => map.serialize_entry(key, $value)?,)+
+                _ => map.serialize_entry(key, value)?,
+            }
+        }
+        $(
+            if !$fields.contains_key($name) {
+                map.serialize_entry($name, $value)?;
+            }
+        )+
+        map.end()
+    }};
+}
+
+#[derive(Debug, Clone)]
 struct ValuesContainer<T> {
     values: Vec<T>,
+    fields: Fields,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+impl<T: Serialize> Serialize for ValuesContainer<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_fields!(serializer, &self.fields, { "values" => &self.values })
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for ValuesContainer<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = Fields::deserialize(deserializer)?;
+        Ok(Self {
+            values: take_field(&mut fields, "values")?,
+            fields,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 struct UnityObjectValue {
     key: String,
     json_key: String,
     full_type: String,
+    fields: Fields,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Serialize for UnityObjectValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_fields!(serializer, &self.fields, {
+            "key" => &self.key,
+            "jsonKey" => &self.json_key,
+            "fullType" => &self.full_type,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for UnityObjectValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = Fields::deserialize(deserializer)?;
+        Ok(Self {
+            key: take_field(&mut fields, "key")?,
+            json_key: take_field(&mut fields, "jsonKey")?,
+            full_type: take_field(&mut fields, "fullType")?,
+            fields,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 struct LargeStringValue {
     key: String,
     val: String,
+    fields: Fields,
+}
+
+impl Serialize for LargeStringValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_fields!(serializer, &self.fields, {
+            "key" => &self.key,
+            "val" => &self.val,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for LargeStringValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = Fields::deserialize(deserializer)?;
+        Ok(Self {
+            key: take_field(&mut fields, "key")?,
+            val: take_field(&mut fields, "val")?,
+            fields,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SrtbFormat {
+    /// Single-line JSON, like the game writes.
+    #[default]
+    Compact,
+    /// Indented JSON with large string values sorted by key, for use with version control. The
+    /// game reads it just fine. Integrated data is stored as strings, which stay on one line
+    /// each: combine with [`SaveOptions::canonical_payloads`] for stable diffs of it.
+    Pretty,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// Amount of previous versions of the destination file to keep. The most recent one is saved
+    /// as `<file>.bak`, older ones as `<file>.bak.2`, `<file>.bak.3` and so on.
+    pub backups: usize,
+    pub format: SrtbFormat,
+    /// Rewrites the data of every integrator in a canonical form, see [`Integrator::canonicalize`].
+    /// Fields the integrators don't know about are kept.
+    ///
+    /// [`Integrator::canonicalize`]: crate::Integrator::canonicalize
+    pub canonical_payloads: bool,
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn backup_path(path: &Path, index: usize) -> PathBuf {
+    if index == 1 {
+        with_suffix(path, ".bak")
+    } else {
+        with_suffix(path, &format!(".bak.{}", index))
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+fn rotate_backups(path: &Path, backups: usize) -> std::io::Result<()> {
+    if backups == 0 || !path.exists() {
+        return Ok(());
+    }
+    for index in (1..backups).rev() {
+        let from = backup_path(path, index);
+        if from.exists() {
+            fs::rename(from, backup_path(path, index + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+/// Makes the name of every temporary file unique within the process.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `data` to a temporary file next to `path`, then moves it over `path`, so that `path`
+/// never ends up partially written. Temporary files are named after the process and a counter,
+/// so that concurrent saves to the same directory don't clash.
+fn write_atomic(path: &Path, data: &[u8], backups: usize) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file path"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = File::create_new(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| rotate_backups(path, backups))
+        .and_then(|_| fs::rename(&temp_path, path))
+        .and_then(|_| sync_parent(path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Flushes the directory entry of `path`, so that a rename into it survives a crash.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+/// Directories can't be opened as files here, renames are as durable as they get.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// An SRTB file. Fields and entries this program does not know about, like `clipInfoCount`, are
+/// preserved so that saving a file that was opened gives back the exact same file.
+#[derive(Debug, Clone)]
 pub struct RawSrtbFile {
     unity_object_values_container: ValuesContainer<UnityObjectValue>,
     large_string_values_container: ValuesContainer<LargeStringValue>,
-    clip_info_count: Option<i32>,
+    fields: Fields,
+    /// Position of the first large string value with a given key.
+    large_string_index: HashMap<String, usize>,
+}
+
+impl Serialize for RawSrtbFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_fields!(serializer, &self.fields, {
+            "unityObjectValuesContainer" => &self.unity_object_values_container,
+            "largeStringValuesContainer" => &self.large_string_values_container,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for RawSrtbFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = Fields::deserialize(deserializer)?;
+        let mut file = Self {
+            unity_object_values_container: take_field(&mut fields, "unityObjectValuesContainer")?,
+            large_string_values_container: take_field(&mut fields, "largeStringValuesContainer")?,
+            fields,
+            large_string_index: HashMap::new(),
+        };
+        file.rebuild_large_string_index();
+        Ok(file)
+    }
 }
 
 impl RawSrtbFile {
@@ -38,8 +325,17 @@ impl RawSrtbFile {
     }
 
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), IntegrationError> {
-        let chart_string = serde_json::to_string(self).map_err(IntegrationError::SerdeJsonError)?;
-        fs::write(path, chart_string).map_err(IntegrationError::IoError)
+        self.save_with_options(path, SaveOptions::default())
+    }
+
+    pub fn save_with_options<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: SaveOptions,
+    ) -> Result<(), IntegrationError> {
+        let chart_bytes = self.to_bytes_with_options(options)?;
+        write_atomic(path.as_ref(), &chart_bytes, options.backups)
+            .map_err(IntegrationError::IoError)
     }
 
     pub fn from_bytes(data: &[u8]) -> Result<Self, IntegrationError> {
@@ -50,42 +346,319 @@ impl RawSrtbFile {
         serde_json::to_vec(self).map_err(IntegrationError::SerdeJsonError)
     }
 
-    pub fn get_large_string_value(&self, key_string: &str) -> Option<String> {
+    /// Serializes the chart according to the format options. Backups are ignored.
+    pub fn to_bytes_with_options(&self, options: SaveOptions) -> Result<Vec<u8>, IntegrationError> {
+        if options.format == SrtbFormat::Compact && !options.canonical_payloads {
+            return self.to_bytes();
+        }
+
+        let mut chart = self.clone();
+        if options.canonical_payloads {
+            for kind in IntegratorKind::ALL {
+                let integrator = kind.integrator();
+                for diff in SpinDifficulty::ALL {
+                    integrator.canonicalize(&mut chart, diff)?;
+                }
+            }
+        }
+        match options.format {
+            SrtbFormat::Compact => chart.to_bytes(),
+            SrtbFormat::Pretty => {
+                chart
+                    .large_string_values_container
+                    .values
+                    .sort_by(|v1, v2| v1.key.cmp(&v2.key));
+                let mut bytes =
+                    serde_json::to_vec_pretty(&chart).map_err(IntegrationError::SerdeJsonError)?;
+                bytes.push(b'\n');
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Iterates over the keys of every unity object.
+    pub(crate) fn unity_object_keys(&self) -> impl Iterator<Item = &str> {
+        self.unity_object_values_container
+            .values
+            .iter()
+            .map(|v| v.key.as_str())
+    }
+
+    /// Returns a top-level field that this program does not otherwise use.
+    pub(crate) fn extra_field<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        serde_json::from_str(self.fields.get(name)?.get()).ok()
+    }
+
+    /// Iterates over the json keys of every unity object.
+    pub(crate) fn json_keys(&self) -> impl Iterator<Item = &str> {
+        self.unity_object_values_container
+            .values
+            .iter()
+            .map(|v| v.json_key.as_str())
+    }
+
+    pub(crate) fn large_string_keys(&self) -> impl Iterator<Item = &str> {
         self.large_string_values_container
             .values
             .iter()
-            .find(|v| v.key == key_string)
-            .map(|v| v.val.clone())
+            .map(|v| v.key.as_str())
     }
 
-    pub fn set_large_string_value(&mut self, key_string: &str, value: &str) {
-        if let Some(val) = self
-            .large_string_values_container
+    /// Iterates over the json keys of every unity object of the given full type.
+    pub(crate) fn json_keys_of_type<'a>(
+        &'a self,
+        full_type: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.unity_object_values_container
             .values
-            .iter_mut()
-            .find(|v| v.key == key_string)
-        {
-            val.val = value.to_string();
+            .iter()
+            .filter(move |v| v.full_type == full_type)
+            .map(|v| v.json_key.as_str())
+    }
+
+    fn rebuild_large_string_index(&mut self) {
+        self.large_string_index.clear();
+        for (i, value) in self.large_string_values_container.values.iter().enumerate() {
+            self.large_string_index
+                .entry(value.key.clone())
+                .or_insert(i);
+        }
+    }
+
+    pub fn get_large_string_value(&self, key_string: &str) -> Option<&str> {
+        self.large_string_index
+            .get(key_string)
+            .map(|&i| self.large_string_values_container.values[i].val.as_str())
+    }
+
+    pub fn set_large_string_value(&mut self, key_string: &str, value: &str) {
+        if let Some(&i) = self.large_string_index.get(key_string) {
+            self.large_string_values_container.values[i].val = value.to_string();
         } else {
+            self.large_string_index.insert(
+                key_string.to_string(),
+                self.large_string_values_container.values.len(),
+            );
             self.large_string_values_container
                 .values
                 .push(LargeStringValue {
                     key: key_string.to_string(),
                     val: value.to_string(),
+                    fields: Fields::new(),
                 });
         }
     }
 
     pub fn remove_large_string_value(&mut self, key_string: &str) {
-        if let Some(i) = self
-            .large_string_values_container
-            .values
-            .iter()
-            .enumerate()
-            .find(|(_, v)| v.key == key_string)
-            .map(|(i, _)| i)
-        {
+        if let Some(&i) = self.large_string_index.get(key_string) {
             self.large_string_values_container.values.remove(i);
+            self.rebuild_large_string_index();
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use super::{SaveOptions, SrtbFormat};
+    use crate::RawSrtbFile;
+
+    fn corpus() -> Vec<PathBuf> {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus");
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "srtb"))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let out_dir = std::env::temp_dir().join("srtb-integration-round-trip");
+        fs::create_dir_all(&out_dir).unwrap();
+        let files = corpus();
+        assert!(!files.is_empty());
+        for path in files {
+            let original = fs::read(&path).unwrap();
+            let chart = RawSrtbFile::open(&path).unwrap();
+            let out_path = out_dir.join(path.file_name().unwrap());
+            chart.save(&out_path).unwrap();
+            let saved = fs::read(&out_path).unwrap();
+            assert!(original == saved, "{} changed on save", path.display());
+            assert_eq!(chart.to_bytes().unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn only_touched_key_changes() {
+        for path in corpus() {
+            let original = fs::read_to_string(&path).unwrap();
+            let mut chart = RawSrtbFile::open(&path).unwrap();
+            let key = "SO_TrackData_TrackData_Normal";
+            let old_value = chart.get_large_string_value(key).unwrap().to_string();
+            let new_value = r#"{"difficultyType":3,"notes":[]}"#;
+            chart.set_large_string_value(key, new_value);
+
+            let expected = original.replace(
+                &serde_json::to_string(&old_value).unwrap(),
+                &serde_json::to_string(new_value).unwrap(),
+            );
+            let saved = String::from_utf8(chart.to_bytes().unwrap()).unwrap();
+            assert_eq!(saved, expected, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn rotating_backups() {
+        let dir = std::env::temp_dir().join("srtb-integration-backups");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chart.srtb");
+        let options = SaveOptions {
+            backups: 2,
+            ..Default::default()
+        };
+
+        let mut chart = RawSrtbFile::open(&corpus()[0]).unwrap();
+        let mut versions = vec![];
+        for i in 0..4 {
+            chart.set_large_string_value("Version", &i.to_string());
+            chart.save_with_options(&path, options).unwrap();
+            versions.push(fs::read(&path).unwrap());
+        }
+
+        assert_eq!(fs::read(&path).unwrap(), versions[3]);
+        assert_eq!(fs::read(dir.join("chart.srtb.bak")).unwrap(), versions[2]);
+        assert_eq!(fs::read(dir.join("chart.srtb.bak.2")).unwrap(), versions[1]);
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["chart.srtb", "chart.srtb.bak", "chart.srtb.bak.2"]
+        );
+    }
+
+    #[test]
+    fn concurrent_saves() {
+        let dir = std::env::temp_dir().join("srtb-integration-concurrent");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let chart = RawSrtbFile::open(&corpus()[0]).unwrap();
+        let path = dir.join("chart.srtb");
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..20 {
+                        chart.save(&path).unwrap();
+                    }
+                });
+            }
+        });
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files, vec!["chart.srtb"]);
+        assert_eq!(fs::read(&path).unwrap(), chart.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn pretty_output() {
+        let options = SaveOptions {
+            format: SrtbFormat::Pretty,
+            ..Default::default()
+        };
+        for path in corpus() {
+            let chart = RawSrtbFile::open(&path).unwrap();
+            let pretty = chart.to_bytes_with_options(options).unwrap();
+            let pretty_chart = RawSrtbFile::from_bytes(&pretty).unwrap();
+            assert_eq!(pretty_chart.to_bytes_with_options(options).unwrap(), pretty);
+
+            let keys: Vec<_> = pretty_chart.large_string_keys().collect();
+            let mut sorted_keys = keys.clone();
+            sorted_keys.sort();
+            assert_eq!(keys, sorted_keys);
+            for key in chart.large_string_keys() {
+                assert_eq!(
+                    chart.get_large_string_value(key),
+                    pretty_chart.get_large_string_value(key)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_payloads() {
+        let mut chart = RawSrtbFile::open(&corpus()[0]).unwrap();
+        chart.set_large_string_value(
+            "SpeedHelper_SpeedTriggers",
+            r#"{ "Triggers": [{"InterpolateToNextTrigger":true,"SpeedMultiplier":2,"Time":1},
+            {"Time":0,"SpeedMultiplier":1,"InterpolateToNextTrigger":false}] }"#,
+        );
+        let options = SaveOptions {
+            canonical_payloads: true,
+            ..Default::default()
+        };
+        let chart =
+            RawSrtbFile::from_bytes(&chart.to_bytes_with_options(options).unwrap()).unwrap();
+        assert_eq!(
+            chart
+                .get_large_string_value("SpeedHelper_SpeedTriggers")
+                .unwrap(),
+            r#"{"Triggers":[{"Time":0.0,"SpeedMultiplier":1.0,"InterpolateToNextTrigger":false},{"Time":1.0,"SpeedMultiplier":2.0,"InterpolateToNextTrigger":true}]}"#
+        );
+    }
+
+    #[test]
+    fn canonical_payloads_keep_unknown_fields() {
+        let mut chart = RawSrtbFile::open(&corpus()[0]).unwrap();
+        chart.set_large_string_value(
+            "SpeedHelper_SpeedTriggers",
+            r#"{"Triggers":[{"Time":1,"SpeedMultiplier":2,"InterpolateToNextTrigger":true,"Ease":"x"},
+            {"Time":0,"SpeedMultiplier":1,"InterpolateToNextTrigger":false}],"Author":"me"}"#,
+        );
+        let options = SaveOptions {
+            canonical_payloads: true,
+            ..Default::default()
+        };
+        let chart =
+            RawSrtbFile::from_bytes(&chart.to_bytes_with_options(options).unwrap()).unwrap();
+        assert_eq!(
+            chart
+                .get_large_string_value("SpeedHelper_SpeedTriggers")
+                .unwrap(),
+            r#"{"Triggers":[{"Time":0.0,"SpeedMultiplier":1.0,"InterpolateToNextTrigger":false},{"Time":1.0,"SpeedMultiplier":2.0,"InterpolateToNextTrigger":true,"Ease":"x"}],"Author":"me"}"#
+        );
+    }
+
+    #[test]
+    fn large_string_index() {
+        let chart = r#"{"unityObjectValuesContainer":{"values":[]},"largeStringValuesContainer":{"values":[
+            {"key":"A","val":"1"},{"key":"B","val":"2"},{"key":"A","val":"3"},{"key":"C","val":"4"}
+        ]}}"#;
+        let mut chart = RawSrtbFile::from_bytes(chart.as_bytes()).unwrap();
+        assert_eq!(chart.get_large_string_value("A"), Some("1"));
+
+        chart.remove_large_string_value("B");
+        assert_eq!(chart.get_large_string_value("B"), None);
+        assert_eq!(chart.get_large_string_value("C"), Some("4"));
+
+        chart.remove_large_string_value("A");
+        assert_eq!(chart.get_large_string_value("A"), Some("3"));
+
+        chart.set_large_string_value("D", "5");
+        chart.set_large_string_value("A", "6");
+        assert_eq!(chart.get_large_string_value("D"), Some("5"));
+        assert_eq!(chart.get_large_string_value("A"), Some("6"));
+        assert_eq!(
+            chart.large_string_keys().collect::<Vec<_>>(),
+            ["A", "C", "D"]
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{
+    expr::{eval, eval_int, Names},
+    track::{BpmMarker, TimeSignatureMarker},
+    ClipInfo, IntegrationError, ParsingError, RawSrtbFile,
+};
+
+/// Resolution of the tick part of a `measure:beat:tick` time.
+pub const TICKS_PER_BEAT: u32 = 480;
+
+/// Converts between seconds and beats using a clip's BPM and time signature markers. Beat 0 is
+/// at the first BPM marker, and a bar holds `ticksPerBar` notes of length `1 / tickDivisor`, so
+/// a 6/8 bar is 3 beats long.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TimingMap {
+    /// Start beat, start time and beat length of every BPM section.
+    sections: Vec<(f64, f64, f64)>,
+    /// Start measure (counting from 0), start beat and beats per bar of every time signature
+    /// section.
+    bars: Vec<(i64, f64, f64)>,
+}
+
+impl TimingMap {
+    pub fn new(clip: &ClipInfo) -> Self {
+        let mut bpm_markers: Vec<&BpmMarker> = clip
+            .bpm_markers
+            .iter()
+            .filter(|m| m.beat_length > 0.)
+            .collect();
+        bpm_markers.sort_by(|m1, m2| m1.clip_time.total_cmp(&m2.clip_time));
+        let mut sections: Vec<(f64, f64, f64)> = vec![];
+        for marker in bpm_markers {
+            let beat = match sections.last() {
+                Some(&(beat, time, length)) => beat + (marker.clip_time as f64 - time) / length,
+                None => 0.,
+            };
+            sections.push((beat, marker.clip_time as f64, marker.beat_length as f64));
+        }
+
+        let mut signature_markers: Vec<&TimeSignatureMarker> =
+            clip.time_signature_markers.iter().collect();
+        signature_markers.sort_by_key(|m| m.starting_beat);
+        let mut bars: Vec<(i64, f64, f64)> = vec![];
+        for marker in signature_markers {
+            let ticks_per_bar = if marker.ticks_per_bar > 0 {
+                marker.ticks_per_bar as f64
+            } else {
+                4.
+            };
+            let tick_divisor = if marker.tick_divisor > 0 {
+                marker.tick_divisor as f64
+            } else {
+                4.
+            };
+            let beats_per_bar = ticks_per_bar * 4. / tick_divisor;
+            let beat = marker.starting_beat as f64;
+            let measure = match bars.last() {
+                Some(&(measure, start, length)) => {
+                    measure + ((beat - start) / length).ceil() as i64
+                }
+                None => 0,
+            };
+            bars.push((measure, beat, beats_per_bar));
+        }
+
+        Self { sections, bars }
+    }
+
+    /// Returns `None` if there are no BPM markers.
+    pub fn beats_to_seconds(&self, beats: f32) -> Option<f32> {
+        let beats = beats as f64;
+        let &(start, time, length) = self
+            .sections
+            .iter()
+            .rev()
+            .find(|(start, _, _)| *start <= beats)
+            .or(self.sections.first())?;
+        Some((time + (beats - start) * length) as f32)
+    }
+
+    /// Returns `None` if there are no BPM markers.
+    pub fn seconds_to_beats(&self, seconds: f32) -> Option<f32> {
+        let seconds = seconds as f64;
+        let &(start, time, length) = self
+            .sections
+            .iter()
+            .rev()
+            .find(|(_, time, _)| *time <= seconds)
+            .or(self.sections.first())?;
+        Some((start + (seconds - time) / length) as f32)
+    }
+
+    /// Beat at which a `measure:beat:tick` position lies. Measures and beats count from 1. Without
+    /// time signature markers, bars are 4 beats long.
+    pub fn position_to_beats(&self, measure: i64, beat: f32, tick: f32) -> f32 {
+        let (start_measure, start, length) = self
+            .bars
+            .iter()
+            .rev()
+            .find(|(start_measure, _, _)| *start_measure < measure)
+            .or(self.bars.first())
+            .copied()
+            .unwrap_or((0, 0., 4.));
+        let beats = start
+            + (measure - 1 - start_measure) as f64 * length
+            + (beat - 1.) as f64
+            + tick as f64 / TICKS_PER_BEAT as f64;
+        beats as f32
+    }
+}
+
+impl RawSrtbFile {
+    /// Timing of the chart's first clip. The map is empty if the chart has no clip. Charts with
+    /// several clips are timed by the first one only.
+    pub fn timing_map(&self) -> Result<TimingMap, IntegrationError> {
+        Ok(self
+            .clip_infos()?
+            .first()
+            .map(TimingMap::new)
+            .unwrap_or_default())
+    }
+}
+
+/// A time or interval as written in a script: seconds (`1.5`), beats (`6b`), or for times only a
+/// `measure:beat:tick` position (`2:1:240`, the tick being optional).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ScriptTime {
+    Seconds(f32),
+    Beats(f32),
+}
+
+impl ScriptTime {
+    pub(crate) fn parse(
+        time: &str,
+        timing: &TimingMap,
+        names: &Names,
+    ) -> Result<Self, ParsingError> {
+        if !time.contains(':') {
+            return Self::parse_interval(time, names);
+        }
+        let parts: Vec<_> = time.split(':').collect();
+        if !(2..=3).contains(&parts.len()) {
+            return Err(ParsingError::InvalidTime(time.into()));
+        }
+        let measure = eval_int(parts[0], names)?;
+        let beat = eval(parts[1], names)?;
+        let tick = match parts.get(2) {
+            Some(tick) => eval(tick, names)?,
+            None => 0.,
+        };
+        Ok(Self::Beats(timing.position_to_beats(
+            measure as i64,
+            beat,
+            tick,
+        )))
+    }
+
+    /// Parses a number of seconds or, with a `b` suffix after a number or parenthesis, beats.
+    pub(crate) fn parse_interval(time: &str, names: &Names) -> Result<Self, ParsingError> {
+        if let Some(beats) = time.strip_suffix('b') {
+            if beats.ends_with(|c: char| c.is_ascii_digit() || c == '.' || c == ')') {
+                return eval(beats, names).map(Self::Beats);
+            }
+        }
+        eval(time, names).map(Self::Seconds)
+    }
+}
+
+/// Resolves `time` moved by each repeat interval times its iteration count to seconds. Beat
+/// offsets are added in beats, so that repeats follow tempo changes.
+pub(crate) fn resolve_time(
+    timing: &TimingMap,
+    time: ScriptTime,
+    repeat_intervals: &[ScriptTime],
+    current_iterations: &[i32],
+) -> Result<f32, ParsingError> {
+    let (mut seconds, mut beats) = match time {
+        ScriptTime::Seconds(seconds) => (seconds, None),
+        ScriptTime::Beats(beats) => (0., Some(beats)),
+    };
+    for (interval, iterations) in repeat_intervals.iter().zip(current_iterations) {
+        match interval {
+            ScriptTime::Seconds(interval) => seconds += interval * *iterations as f32,
+            ScriptTime::Beats(interval) => {
+                *beats.get_or_insert(0.) += interval * *iterations as f32;
+            }
+        }
+    }
+    let Some(beats) = beats else {
+        return Ok(seconds);
+    };
+    let beats = match time {
+        ScriptTime::Seconds(start) => {
+            seconds -= start;
+            timing.seconds_to_beats(start).map(|start| start + beats)
+        }
+        ScriptTime::Beats(_) => Some(beats),
+    };
+    beats
+        .and_then(|beats| timing.beats_to_seconds(beats))
+        .map(|time| time + seconds)
+        .ok_or(ParsingError::MissingBpmMarkers)
+}
+
+/// Everything needed to resolve script times at the current point of a script.
+pub(crate) struct TimeContext<'a> {
+    pub(crate) timing: &'a TimingMap,
+    pub(crate) names: &'a Names,
+    pub(crate) repeat_intervals: &'a [ScriptTime],
+    pub(crate) current_iterations: &'a [i32],
+}
+
+impl TimeContext<'_> {
+    pub(crate) fn time(&self, time: &str) -> Result<f32, ParsingError> {
+        let time = ScriptTime::parse(time, self.timing, self.names)?;
+        resolve_time(
+            self.timing,
+            time,
+            self.repeat_intervals,
+            self.current_iterations,
+        )
+    }
+
+    /// Resolves the start and end of something lasting `duration` from `start`.
+    pub(crate) fn span(&self, start: &str, duration: &str) -> Result<(f32, f32), ParsingError> {
+        let start = ScriptTime::parse(start, self.timing, self.names)?;
+        let duration = ScriptTime::parse_interval(duration, self.names)?;
+        let intervals: Vec<_> = self
+            .repeat_intervals
+            .iter()
+            .copied()
+            .chain([duration])
+            .collect();
+        let iterations: Vec<_> = self.current_iterations.iter().copied().chain([1]).collect();
+        Ok((
+            resolve_time(
+                self.timing,
+                start,
+                self.repeat_intervals,
+                self.current_iterations,
+            )?,
+            resolve_time(self.timing, start, &intervals, &iterations)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        expr::Names,
+        timing::{resolve_time, ScriptTime},
+        track::{test::CHART, TimeSignatureMarker},
+        ClipInfo, ParsingError, RawSrtbFile, TimingMap,
+    };
+
+    #[test]
+    fn convert_beats() {
+        let chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let timing = chart.timing_map().unwrap();
+        // 120 BPM until 8s (beat 16), then 240 BPM.
+        assert_eq!(timing.beats_to_seconds(4.), Some(2.));
+        assert_eq!(timing.beats_to_seconds(20.), Some(9.));
+        assert_eq!(timing.seconds_to_beats(9.), Some(20.));
+        assert_eq!(timing.seconds_to_beats(2.), Some(4.));
+        assert_eq!(timing.position_to_beats(5, 1., 0.), 16.);
+        assert_eq!(timing.position_to_beats(2, 3., 240.), 6.5);
+        assert_eq!(TimingMap::default().beats_to_seconds(1.), None);
+    }
+
+    #[test]
+    fn compound_time_signature() {
+        let signature = |starting_beat, ticks_per_bar, tick_divisor| TimeSignatureMarker {
+            starting_beat,
+            ticks_per_bar,
+            tick_divisor,
+            ..Default::default()
+        };
+        // Two bars of 6/8 (3 beats each), then 4/4, and a divisor of 0 read as 4.
+        let timing = TimingMap::new(&ClipInfo {
+            time_signature_markers: vec![signature(0, 6, 8), signature(6, 4, 4)],
+            ..Default::default()
+        });
+        assert_eq!(timing.position_to_beats(2, 1., 0.), 3.);
+        assert_eq!(timing.position_to_beats(3, 1., 0.), 6.);
+        assert_eq!(timing.position_to_beats(4, 1., 0.), 10.);
+
+        let timing = TimingMap::new(&ClipInfo {
+            time_signature_markers: vec![signature(0, 3, 0)],
+            ..Default::default()
+        });
+        assert_eq!(timing.position_to_beats(2, 1., 0.), 3.);
+    }
+
+    #[test]
+    fn script_times() {
+        let chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let timing = chart.timing_map().unwrap();
+        let parse = |time| ScriptTime::parse(time, &timing, &Names::new()).unwrap();
+        let resolve = |time, intervals: &[ScriptTime], iterations: &[i32]| {
+            resolve_time(&timing, time, intervals, iterations).unwrap()
+        };
+
+        assert_eq!(resolve(parse("1.5"), &[], &[]), 1.5);
+        assert_eq!(resolve(parse("16b"), &[], &[]), 8.);
+        assert_eq!(resolve(parse("5:1"), &[], &[]), 8.);
+        assert_eq!(resolve(parse("4:1"), &[ScriptTime::Beats(2.)], &[2]), 8.);
+        assert_eq!(resolve(parse("8"), &[ScriptTime::Beats(4.)], &[1]), 9.);
+        assert_eq!(
+            resolve(
+                parse("0b"),
+                &[ScriptTime::Seconds(0.5), ScriptTime::Beats(1.)],
+                &[1, 2]
+            ),
+            1.5
+        );
+
+        assert_eq!(
+            ScriptTime::parse("1:x:2:3", &timing, &Names::new()),
+            Err(ParsingError::InvalidTime("1:x:2:3".into()))
+        );
+        assert_eq!(
+            resolve_time(&TimingMap::default(), parse("1b"), &[], &[]),
+            Err(ParsingError::MissingBpmMarkers)
+        );
+    }
+}
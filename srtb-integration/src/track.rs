@@ -0,0 +1,425 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{IntegrationError, RawSrtbFile, SpinDifficulty};
+
+pub(crate) const TRACK_INFO_TYPE: &str = "TrackInfo";
+pub(crate) const TRACK_DATA_TYPE: &str = "TrackData";
+pub(crate) const CLIP_INFO_TYPE: &str = "ClipInfo";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrackInfo {
+    pub title: String,
+    pub subtitle: String,
+    pub artist_name: String,
+    pub charter: String,
+    pub difficulties: Vec<TrackInfoDifficulty>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrackInfoDifficulty {
+    #[serde(rename = "_active")]
+    pub active: bool,
+    pub difficulty: i32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl TrackInfoDifficulty {
+    pub fn spin_difficulty(&self) -> Option<SpinDifficulty> {
+        SpinDifficulty::from_difficulty_type(self.difficulty)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrackData {
+    pub difficulty_type: i32,
+    pub notes: Vec<Note>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl TrackData {
+    pub fn spin_difficulty(&self) -> Option<SpinDifficulty> {
+        SpinDifficulty::from_difficulty_type(self.difficulty_type)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Note {
+    pub time: f32,
+    #[serde(rename = "type")]
+    pub note_type: i32,
+    pub color_index: i32,
+    pub column: i32,
+    #[serde(rename = "m_size")]
+    pub size: i32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClipInfo {
+    pub bpm_markers: Vec<BpmMarker>,
+    pub time_signature_markers: Vec<TimeSignatureMarker>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BpmMarker {
+    pub clip_time: f32,
+    pub beat_length: f32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TimeSignatureMarker {
+    pub clip_time: f32,
+    pub starting_beat: i32,
+    pub ticks_per_bar: i32,
+    pub tick_divisor: i32,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Only the part of a track data object needed to know its difficulty, to avoid parsing notes.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrackDataHeader {
+    difficulty_type: i32,
+}
+
+fn object_fields<T: Serialize>(object: &T) -> Result<Map<String, Value>, IntegrationError> {
+    serde_json::to_value(object)
+        .and_then(serde_json::from_value)
+        .map_err(IntegrationError::SerdeJsonError)
+}
+
+impl RawSrtbFile {
+    fn read_track_object<T: DeserializeOwned>(
+        &self,
+        json_key: &str,
+    ) -> Result<T, IntegrationError> {
+        let value = self
+            .get_large_string_value(json_key)
+            .ok_or_else(|| IntegrationError::DanglingJsonKey(json_key.into()))?;
+        serde_json::from_str(value).map_err(IntegrationError::SerdeJsonError)
+    }
+
+    /// Writes `object` over the stored one, replacing only the keys whose value changed so that
+    /// the others keep their position and formatting.
+    fn write_track_object<T: Serialize + DeserializeOwned>(
+        &mut self,
+        json_key: &str,
+        object: &T,
+    ) -> Result<(), IntegrationError> {
+        let fields = object_fields(object)?;
+        let (mut stored, before) = match self.get_large_string_value(json_key) {
+            Some(value) => (
+                serde_json::from_str(value).map_err(IntegrationError::SerdeJsonError)?,
+                object_fields(&self.read_track_object::<T>(json_key)?)?,
+            ),
+            None => (Map::new(), Map::new()),
+        };
+
+        let count = stored.len();
+        stored.retain(|key, _| fields.contains_key(key));
+        let mut changed = stored.len() != count;
+        for (key, value) in fields {
+            if before.get(&key) != Some(&value) {
+                stored.insert(key, value);
+                changed = true;
+            }
+        }
+
+        if changed {
+            let value = serde_json::to_string(&stored).map_err(IntegrationError::SerdeJsonError)?;
+            self.set_large_string_value(json_key, &value);
+        }
+        Ok(())
+    }
+
+    fn track_data_entries(&self) -> Result<Vec<(String, TrackData)>, IntegrationError> {
+        self.json_keys_of_type(TRACK_DATA_TYPE)
+            .map(|key| Ok((key.to_string(), self.read_track_object(key)?)))
+            .collect()
+    }
+
+    pub fn track_info(&self) -> Result<TrackInfo, IntegrationError> {
+        let key = self
+            .json_keys_of_type(TRACK_INFO_TYPE)
+            .next()
+            .ok_or_else(|| IntegrationError::MissingUnityObject(TRACK_INFO_TYPE.into()))?;
+        self.read_track_object(key)
+    }
+
+    pub fn set_track_info(&mut self, info: &TrackInfo) -> Result<(), IntegrationError> {
+        let key = self
+            .json_keys_of_type(TRACK_INFO_TYPE)
+            .next()
+            .ok_or_else(|| IntegrationError::MissingUnityObject(TRACK_INFO_TYPE.into()))?
+            .to_string();
+        self.write_track_object(&key, info)
+    }
+
+    /// Reads the track info, lets `edit` change it and writes it back.
+    pub fn edit_track_info(
+        &mut self,
+        edit: impl FnOnce(&mut TrackInfo),
+    ) -> Result<(), IntegrationError> {
+        let mut info = self.track_info()?;
+        edit(&mut info);
+        self.set_track_info(&info)
+    }
+
+    pub fn title(&self) -> Result<String, IntegrationError> {
+        Ok(self.track_info()?.title)
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<(), IntegrationError> {
+        self.edit_track_info(|info| info.title = title.into())
+    }
+
+    pub fn subtitle(&self) -> Result<String, IntegrationError> {
+        Ok(self.track_info()?.subtitle)
+    }
+
+    pub fn set_subtitle(&mut self, subtitle: &str) -> Result<(), IntegrationError> {
+        self.edit_track_info(|info| info.subtitle = subtitle.into())
+    }
+
+    pub fn artist(&self) -> Result<String, IntegrationError> {
+        Ok(self.track_info()?.artist_name)
+    }
+
+    pub fn set_artist(&mut self, artist: &str) -> Result<(), IntegrationError> {
+        self.edit_track_info(|info| info.artist_name = artist.into())
+    }
+
+    pub fn charter(&self) -> Result<String, IntegrationError> {
+        Ok(self.track_info()?.charter)
+    }
+
+    pub fn set_charter(&mut self, charter: &str) -> Result<(), IntegrationError> {
+        self.edit_track_info(|info| info.charter = charter.into())
+    }
+
+    /// Returns the difficulties listed in the track info, which is what the game shows.
+    pub fn difficulty_list(&self) -> Result<Vec<TrackInfoDifficulty>, IntegrationError> {
+        Ok(self.track_info()?.difficulties)
+    }
+
+    pub fn set_difficulty_list(
+        &mut self,
+        difficulties: Vec<TrackInfoDifficulty>,
+    ) -> Result<(), IntegrationError> {
+        self.edit_track_info(|info| info.difficulties = difficulties)
+    }
+
+    /// Returns the track data of every difficulty, in the order the chart references them.
+    pub fn all_track_data(&self) -> Result<Vec<TrackData>, IntegrationError> {
+        Ok(self
+            .track_data_entries()?
+            .into_iter()
+            .map(|(_, data)| data)
+            .collect())
+    }
+
+    pub fn track_data(&self, diff: SpinDifficulty) -> Result<Option<TrackData>, IntegrationError> {
+        Ok(self
+            .track_data_entries()?
+            .into_iter()
+            .map(|(_, data)| data)
+            .find(|data| data.spin_difficulty() == Some(diff)))
+    }
+
+    /// Replaces the track data referenced for the difficulty stored in `data`.
+    pub fn set_track_data(&mut self, data: &TrackData) -> Result<(), IntegrationError> {
+        let key = self
+            .track_data_entries()?
+            .into_iter()
+            .find(|(_, d)| d.difficulty_type == data.difficulty_type)
+            .map(|(key, _)| key)
+            .ok_or_else(|| IntegrationError::MissingUnityObject(TRACK_DATA_TYPE.into()))?;
+        self.write_track_object(&key, data)
+    }
+
+    /// Returns the difficulties this chart has track data for, from easiest to hardest.
+    pub fn difficulties(&self) -> Result<Vec<SpinDifficulty>, IntegrationError> {
+        let mut diffs = vec![];
+        for key in self.json_keys_of_type(TRACK_DATA_TYPE) {
+            let header: TrackDataHeader = self.read_track_object(key)?;
+            if let Some(diff) = SpinDifficulty::from_difficulty_type(header.difficulty_type) {
+                diffs.push(diff);
+            }
+        }
+        Ok(SpinDifficulty::ALL
+            .into_iter()
+            .filter(|diff| diffs.contains(diff))
+            .collect())
+    }
+
+    /// Checks that data can be integrated for `diff`. [`SpinDifficulty::AllDifficulties`] is
+    /// always accepted.
+    pub fn ensure_difficulty(&self, diff: SpinDifficulty) -> Result<(), IntegrationError> {
+        if diff == SpinDifficulty::AllDifficulties || self.difficulties()?.contains(&diff) {
+            Ok(())
+        } else {
+            Err(IntegrationError::MissingDifficulty(diff))
+        }
+    }
+
+    pub fn clip_infos(&self) -> Result<Vec<ClipInfo>, IntegrationError> {
+        self.json_keys_of_type(CLIP_INFO_TYPE)
+            .map(|key| self.read_track_object(key))
+            .collect()
+    }
+
+    pub fn set_clip_info(&mut self, index: usize, info: &ClipInfo) -> Result<(), IntegrationError> {
+        let key = self
+            .json_keys_of_type(CLIP_INFO_TYPE)
+            .nth(index)
+            .map(String::from)
+            .ok_or_else(|| IntegrationError::MissingUnityObject(CLIP_INFO_TYPE.into()))?;
+        self.write_track_object(&key, info)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::{IntegrationError, RawSrtbFile, SpinDifficulty};
+
+    pub(crate) const CHART: &str = r#"{"unityObjectValuesContainer":{"values":[{"key":"SO_TrackInfo_TrackInfo","jsonKey":"SO_TrackInfo_TrackInfo","fullType":"TrackInfo"},{"key":"SO_TrackData_TrackData_Normal","jsonKey":"SO_TrackData_TrackData_Normal","fullType":"TrackData"},{"key":"SO_TrackData_TrackData_XD","jsonKey":"SO_TrackData_TrackData_XD","fullType":"TrackData"},{"key":"SO_ClipInfo_ClipInfo_0","jsonKey":"SO_ClipInfo_ClipInfo_0","fullType":"ClipInfo"}]},"largeStringValuesContainer":{"values":[{"key":"SO_TrackInfo_TrackInfo","val":"{\"artistName\":\"Artist\",\"title\":\"Title\",\"subtitle\":\"Sub\",\"charter\":\"Charter\",\"difficulties\":[{\"_active\":true,\"difficulty\":3,\"assetName\":\"TrackData_Normal\"},{\"_active\":true,\"difficulty\":6,\"assetName\":\"TrackData_XD\"}],\"albumArtReference\":{\"assetName\":\"cover\"}}"},{"key":"SO_TrackData_TrackData_Normal","val":"{\"difficultyType\":3,\"notes\":[{\"time\":1.0,\"type\":0,\"colorIndex\":0,\"column\":0,\"m_size\":0},{\"time\":2.5,\"type\":1,\"colorIndex\":1,\"column\":2,\"m_size\":0}],\"difficultyRating\":12}"},{"key":"SO_TrackData_TrackData_XD","val":"{\"difficultyType\":6,\"notes\":[],\"difficultyRating\":50}"},{"key":"SO_ClipInfo_ClipInfo_0","val":"{\"clipAssetReference\":{\"assetName\":\"audio\"},\"bpmMarkers\":[{\"clipTime\":0.0,\"beatLength\":0.5},{\"clipTime\":8.0,\"beatLength\":0.25}],\"timeSignatureMarkers\":[{\"clipTime\":0.0,\"startingBeat\":0,\"ticksPerBar\":4,\"tickDivisor\":4}]}"}]},"clipInfoCount":1}"#;
+
+    #[test]
+    fn read_track_model() {
+        let chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+
+        let info = chart.track_info().unwrap();
+        assert_eq!(info.title, "Title");
+        assert_eq!(info.artist_name, "Artist");
+        assert_eq!(info.difficulties.len(), 2);
+        assert_eq!(
+            info.difficulties[1].spin_difficulty(),
+            Some(SpinDifficulty::XD)
+        );
+
+        let normal = chart.track_data(SpinDifficulty::Normal).unwrap().unwrap();
+        assert_eq!(normal.notes.len(), 2);
+        assert_eq!(normal.notes[1].time, 2.5);
+        assert_eq!(normal.notes[1].column, 2);
+        assert!(chart.track_data(SpinDifficulty::Easy).unwrap().is_none());
+
+        let clips = chart.clip_infos().unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].bpm_markers[1].beat_length, 0.25);
+        assert_eq!(clips[0].time_signature_markers[0].ticks_per_bar, 4);
+    }
+
+    #[test]
+    fn detect_difficulties() {
+        let chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        assert_eq!(
+            chart.difficulties().unwrap(),
+            vec![SpinDifficulty::Normal, SpinDifficulty::XD]
+        );
+        assert!(chart.ensure_difficulty(SpinDifficulty::XD).is_ok());
+        assert!(chart
+            .ensure_difficulty(SpinDifficulty::AllDifficulties)
+            .is_ok());
+        assert!(matches!(
+            chart.ensure_difficulty(SpinDifficulty::RemiXD),
+            Err(IntegrationError::MissingDifficulty(SpinDifficulty::RemiXD))
+        ));
+    }
+
+    #[test]
+    fn edit_metadata() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        assert_eq!(chart.title().unwrap(), "Title");
+        assert_eq!(chart.subtitle().unwrap(), "Sub");
+        assert_eq!(chart.artist().unwrap(), "Artist");
+        assert_eq!(chart.charter().unwrap(), "Charter");
+
+        chart.set_title("New Title").unwrap();
+        chart.set_subtitle("").unwrap();
+        chart.set_artist("New Artist").unwrap();
+        chart.set_charter("New Charter").unwrap();
+        let mut difficulties = chart.difficulty_list().unwrap();
+        difficulties[0].active = false;
+        chart.set_difficulty_list(difficulties).unwrap();
+
+        let info = chart.track_info().unwrap();
+        assert_eq!(info.title, "New Title");
+        assert_eq!(info.subtitle, "");
+        assert_eq!(info.artist_name, "New Artist");
+        assert_eq!(info.charter, "New Charter");
+        assert!(!info.difficulties[0].active);
+        assert!(info.difficulties[1].active);
+        assert_eq!(info.difficulties[0].extra["assetName"], "TrackData_Normal");
+        assert_eq!(info.extra["albumArtReference"]["assetName"], "cover");
+    }
+
+    #[test]
+    fn edit_keeps_key_order() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let original = chart
+            .get_large_string_value("SO_TrackInfo_TrackInfo")
+            .unwrap()
+            .to_string();
+
+        chart.set_title("Title").unwrap();
+        assert_eq!(
+            chart.get_large_string_value("SO_TrackInfo_TrackInfo"),
+            Some(original.as_str())
+        );
+
+        chart.set_title("New Title").unwrap();
+        assert_eq!(
+            chart.get_large_string_value("SO_TrackInfo_TrackInfo"),
+            Some(original.replace("\"Title\"", "\"New Title\"").as_str())
+        );
+    }
+
+    #[test]
+    fn write_track_model() {
+        let mut chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let untouched = chart
+            .get_large_string_value("SO_TrackData_TrackData_XD")
+            .unwrap()
+            .to_string();
+
+        let mut normal = chart.track_data(SpinDifficulty::Normal).unwrap().unwrap();
+        normal.notes.pop();
+        chart.set_track_data(&normal).unwrap();
+
+        let mut clip = chart.clip_infos().unwrap().remove(0);
+        clip.bpm_markers[0].beat_length = 0.4;
+        chart.set_clip_info(0, &clip).unwrap();
+
+        let normal = chart.track_data(SpinDifficulty::Normal).unwrap().unwrap();
+        assert_eq!(normal.notes.len(), 1);
+        assert_eq!(normal.extra["difficultyRating"], 12);
+        assert_eq!(
+            chart.clip_infos().unwrap()[0].bpm_markers[0].beat_length,
+            0.4
+        );
+        assert_eq!(
+            chart
+                .get_large_string_value("SO_TrackData_TrackData_XD")
+                .unwrap(),
+            untouched
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+
+use crate::{source_key, IntegrationError, IntegratorKind, RawSrtbFile, SpinDifficulty};
+
+/// Which integration data to copy. An empty list selects everything.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TransplantSelection {
+    pub integrators: Vec<IntegratorKind>,
+    pub difficulties: Vec<SpinDifficulty>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TransplantReport {
+    /// Every key that was copied.
+    pub copied: Vec<String>,
+    /// Copied keys that already existed in the target chart with a different value.
+    pub overwritten: Vec<String>,
+    /// Keys that were not copied because the target chart lacks their difficulty.
+    pub skipped: Vec<String>,
+}
+
+/// Finds the integrator and difficulty a key belongs to. The difficulty is `None` for keys that
+/// use the integrator's prefix without being one of its known keys.
+fn classify_key(key: &str) -> Option<(IntegratorKind, Option<SpinDifficulty>)> {
+    let kind = IntegratorKind::ALL
+        .into_iter()
+        .find(|kind| key.starts_with(kind.mod_prefix()))?;
+    let integrator = kind.integrator();
+    let diff = SpinDifficulty::ALL.into_iter().find(|&diff| {
+        let diff_key = integrator.srtb_key(diff);
+        key == diff_key || key == source_key(&diff_key)
+    });
+    Some((kind, diff))
+}
+
+impl RawSrtbFile {
+    /// Copies the integration data selected from `source` into this chart, for example to carry
+    /// it over to a chart that was re-exported from the game editor.
+    pub fn transplant_integration_data(
+        &mut self,
+        source: &RawSrtbFile,
+        selection: &TransplantSelection,
+    ) -> Result<TransplantReport, IntegrationError> {
+        let target_diffs = self.difficulties()?;
+        let mut report = TransplantReport::default();
+        let mut seen = HashSet::new();
+        for key in source.large_string_keys() {
+            if !seen.insert(key) {
+                continue;
+            }
+            let Some((kind, diff)) = classify_key(key) else {
+                continue;
+            };
+            if !selection.integrators.is_empty() && !selection.integrators.contains(&kind) {
+                continue;
+            }
+            match diff {
+                Some(diff) => {
+                    if !selection.difficulties.is_empty() && !selection.difficulties.contains(&diff)
+                    {
+                        continue;
+                    }
+                    if diff != SpinDifficulty::AllDifficulties && !target_diffs.contains(&diff) {
+                        report.skipped.push(key.into());
+                        continue;
+                    }
+                }
+                None if !selection.difficulties.is_empty() => continue,
+                None => {}
+            }
+
+            let value = source.get_large_string_value(key).unwrap_or_default();
+            if self
+                .get_large_string_value(key)
+                .is_some_and(|old| old != value)
+            {
+                report.overwritten.push(key.into());
+            }
+            self.set_large_string_value(key, value);
+            report.copied.push(key.into());
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        track::test::CHART, ChromaIntegrator, IntegrateOptions, Integrator, IntegratorKind,
+        RawSrtbFile, SpeedsIntegrator, SpinDifficulty, TransplantSelection,
+    };
+
+    fn source_chart() -> RawSrtbFile {
+        let mut source = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let options = IntegrateOptions {
+            embed_source: true,
+            ..Default::default()
+        };
+        SpeedsIntegrator
+            .integrate_with_options(&mut source, "0 2", SpinDifficulty::Normal, options)
+            .unwrap();
+        ChromaIntegrator
+            .integrate(
+                &mut source,
+                "Instant NoteA 1.0 #ff0000",
+                SpinDifficulty::AllDifficulties,
+            )
+            .unwrap();
+        source.set_large_string_value("SpeedHelper_SpeedTriggers_EASY", "{\"Triggers\":[]}");
+        source.set_large_string_value("SpeenChroma_Settings", "{}");
+        source.set_large_string_value("OtherMod_Data", "{}");
+        source
+    }
+
+    #[test]
+    fn transplant_everything() {
+        let source = source_chart();
+        let mut target = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        SpeedsIntegrator
+            .integrate(&mut target, "0 1", SpinDifficulty::Normal)
+            .unwrap();
+
+        let report = target
+            .transplant_integration_data(&source, &TransplantSelection::default())
+            .unwrap();
+        assert_eq!(
+            report.copied,
+            vec![
+                "SpeedHelper_SpeedTriggers_NORMAL",
+                "SpeedHelper_SpeedTriggers_NORMAL_Source",
+                "SpeenChroma_ChromaTriggers",
+                "SpeenChroma_Settings",
+            ]
+        );
+        assert_eq!(report.overwritten, vec!["SpeedHelper_SpeedTriggers_NORMAL"]);
+        assert_eq!(report.skipped, vec!["SpeedHelper_SpeedTriggers_EASY"]);
+        assert_eq!(
+            SpeedsIntegrator
+                .extract(&target, SpinDifficulty::Normal)
+                .unwrap(),
+            "0 2"
+        );
+        assert!(target.get_large_string_value("OtherMod_Data").is_none());
+    }
+
+    #[test]
+    fn transplant_selection() {
+        let source = source_chart();
+        let mut target = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        let selection = TransplantSelection {
+            integrators: vec![IntegratorKind::Chroma],
+            difficulties: vec![SpinDifficulty::AllDifficulties, SpinDifficulty::Normal],
+        };
+        let report = target
+            .transplant_integration_data(&source, &selection)
+            .unwrap();
+        assert_eq!(report.copied, vec!["SpeenChroma_ChromaTriggers"]);
+        assert!(report.overwritten.is_empty());
+        assert!(report.skipped.is_empty());
+    }
+}
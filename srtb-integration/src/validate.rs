@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    track::{CLIP_INFO_TYPE, TRACK_INFO_TYPE},
+    IntegrationError, IntegratorKind, RawSrtbFile, SpinDifficulty,
+};
+
+/// A structural problem found in a chart.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ValidationFinding {
+    #[error("json key \"{0}\" has no matching large string value")]
+    DanglingJsonKey(String),
+
+    #[error("unity object key \"{0}\" is used more than once")]
+    DuplicateUnityObjectKey(String),
+
+    #[error("large string key \"{0}\" is used more than once, only the first value is used")]
+    DuplicateLargeStringKey(String),
+
+    #[error("large string value \"{key}\" is not valid json: {error}")]
+    MalformedJson { key: String, error: String },
+
+    #[error("{integrator} data in \"{key}\" can't be read: {error}")]
+    InvalidIntegrationData {
+        integrator: IntegratorKind,
+        key: String,
+        error: String,
+    },
+
+    #[error("no track info found")]
+    MissingTrackInfo,
+
+    #[error("clipInfoCount is {declared}, but the chart has {found} clip info(s)")]
+    ClipInfoCountMismatch { declared: i64, found: usize },
+}
+
+impl RawSrtbFile {
+    /// Checks the chart for structural problems that don't prevent it from being opened.
+    /// Returns an empty list if none were found.
+    pub fn validate(&self) -> Vec<ValidationFinding> {
+        let mut findings = vec![];
+
+        let mut seen = HashSet::new();
+        for key in self.unity_object_keys() {
+            if !seen.insert(key) {
+                findings.push(ValidationFinding::DuplicateUnityObjectKey(key.into()));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for key in self.large_string_keys() {
+            if !seen.insert(key) {
+                findings.push(ValidationFinding::DuplicateLargeStringKey(key.into()));
+            }
+        }
+
+        for json_key in self.json_keys() {
+            match self.get_large_string_value(json_key) {
+                Some(value) => {
+                    if let Err(e) = serde_json::from_str::<Value>(value) {
+                        findings.push(ValidationFinding::MalformedJson {
+                            key: json_key.into(),
+                            error: e.to_string(),
+                        });
+                    }
+                }
+                None => findings.push(ValidationFinding::DanglingJsonKey(json_key.into())),
+            }
+        }
+
+        if self.json_keys_of_type(TRACK_INFO_TYPE).next().is_none() {
+            findings.push(ValidationFinding::MissingTrackInfo);
+        }
+
+        let clip_count = self.json_keys_of_type(CLIP_INFO_TYPE).count();
+        if let Some(declared) = self.extra_field::<i64>("clipInfoCount") {
+            if declared != clip_count as i64 {
+                findings.push(ValidationFinding::ClipInfoCountMismatch {
+                    declared,
+                    found: clip_count,
+                });
+            }
+        }
+
+        for kind in IntegratorKind::ALL {
+            let integrator = kind.integrator();
+            for diff in SpinDifficulty::ALL {
+                match integrator.summarize(self, diff) {
+                    Ok(_) | Err(IntegrationError::MissingData) => {}
+                    Err(e) => findings.push(ValidationFinding::InvalidIntegrationData {
+                        integrator: kind,
+                        key: integrator.srtb_key(diff),
+                        error: e.to_string(),
+                    }),
+                }
+            }
+        }
+
+        findings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{track::test::CHART, IntegratorKind, RawSrtbFile, ValidationFinding};
+
+    #[test]
+    fn valid_chart() {
+        let chart = RawSrtbFile::from_bytes(CHART.as_bytes()).unwrap();
+        assert_eq!(chart.validate(), vec![]);
+    }
+
+    #[test]
+    fn structural_problems() {
+        let chart = r#"{"unityObjectValuesContainer":{"values":[
+            {"key":"SO_TrackData_TrackData_Normal","jsonKey":"SO_TrackData_TrackData_Normal","fullType":"TrackData"},
+            {"key":"SO_TrackData_TrackData_Normal","jsonKey":"SO_TrackData_TrackData_Hard","fullType":"TrackData"},
+            {"key":"SO_ClipInfo_ClipInfo_0","jsonKey":"SO_ClipInfo_ClipInfo_0","fullType":"ClipInfo"}
+        ]},"largeStringValuesContainer":{"values":[
+            {"key":"SO_TrackData_TrackData_Normal","val":"{\"difficultyType\":3,\"notes\":[]}"},
+            {"key":"SO_TrackData_TrackData_Normal","val":"{}"},
+            {"key":"SO_ClipInfo_ClipInfo_0","val":"{\"bpmMarkers\":["},
+            {"key":"SpeedHelper_SpeedTriggers_NORMAL","val":"{\"Triggers\":3}"}
+        ]},"clipInfoCount":2}"#;
+        let chart = RawSrtbFile::from_bytes(chart.as_bytes()).unwrap();
+        let findings = chart.validate();
+
+        assert_eq!(findings.len(), 7);
+        assert_eq!(
+            findings[0],
+            ValidationFinding::DuplicateUnityObjectKey("SO_TrackData_TrackData_Normal".into())
+        );
+        assert_eq!(
+            findings[1],
+            ValidationFinding::DuplicateLargeStringKey("SO_TrackData_TrackData_Normal".into())
+        );
+        assert_eq!(
+            findings[2],
+            ValidationFinding::DanglingJsonKey("SO_TrackData_TrackData_Hard".into())
+        );
+        assert!(matches!(
+            &findings[3],
+            ValidationFinding::MalformedJson { key, .. } if key == "SO_ClipInfo_ClipInfo_0"
+        ));
+        assert_eq!(findings[4], ValidationFinding::MissingTrackInfo);
+        assert_eq!(
+            findings[5],
+            ValidationFinding::ClipInfoCountMismatch {
+                declared: 2,
+                found: 1
+            }
+        );
+        assert!(matches!(
+            &findings[6],
+            ValidationFinding::InvalidIntegrationData {
+                integrator: IntegratorKind::Speeds,
+                key,
+                ..
+            } if key == "SpeedHelper_SpeedTriggers_NORMAL"
+        ));
+    }
+}